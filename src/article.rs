@@ -6,8 +6,8 @@ use std::path::Path;
 use tokio::io;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 
-const MARKDOWN_SEPARATOR_LINUX: &'static str = "+++\n";
-const MARKDOWN_SEPARATOR_WINDOWS: &'static str = "+++\r\n";
+const MARKDOWN_SEPARATOR_LINUX: &str = "+++\n";
+const MARKDOWN_SEPARATOR_WINDOWS: &str = "+++\r\n";
 
 #[derive(Debug, Clone, Default)]
 pub struct RawArticle {
@@ -84,7 +84,7 @@ impl RawArticle {
     pub async fn write_to_path(&self, filepath: &Path, url_path: &str) -> Result<(), ArticleWriteError> {
         let mut file = filesystem::WritableFile::open(&filepath)
             .await
-            .or_else(|e| Err(ArticleWriteError::from_file_write_error(e, url_path.to_owned())))?;
+            .map_err(|e| ArticleWriteError::from_file_write_error(e, url_path.to_owned()))?;
         self.write(&mut file.writer)
             .await
             .with_context(|_| filesystem::UnhandlableWriteSnafu { filepath })
@@ -150,7 +150,7 @@ impl ArticleWriteError {
         use tokio::io::ErrorKind;
 
         match &err {
-            FileWriteError::ConflictingWriteInProgress { filepath: _, .. } => ConflictingWriteInProgress { path },
+            FileWriteError::ConflictingWriteInProgress { .. } => ConflictingWriteInProgress { path },
             FileWriteError::UnhandlableWriteError { source, filepath: _ } => match source.kind() {
                 ErrorKind::NotFound | ErrorKind::IsADirectory | ErrorKind::InvalidInput | ErrorKind::InvalidFilename => {
                     InvalidPath { source: err, path }
@@ -0,0 +1,291 @@
+use crate::auth::User;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// The number of bytes read at a time when searching the log from the end.
+const READ_CHUNK: u64 = 64 * 1024;
+
+/// What happened in an audit log event.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    ArticleCreate,
+    ArticleUpdate,
+    LoginSuccess,
+    LoginFailure,
+    Logout,
+    AccountCreate,
+    AccountDelete,
+    AccountRename,
+    AccountDisable,
+    AccountEnable,
+    InviteCreate,
+    PasswordChange,
+    PasswordResetLinkCreate,
+    PasswordReset,
+    TotpEnable,
+    TotpDisable,
+    TokenCreate,
+    TokenRevoke,
+}
+
+impl AuditAction {
+    pub const ALL: &[AuditAction] = &[
+        AuditAction::ArticleCreate,
+        AuditAction::ArticleUpdate,
+        AuditAction::LoginSuccess,
+        AuditAction::LoginFailure,
+        AuditAction::Logout,
+        AuditAction::AccountCreate,
+        AuditAction::AccountDelete,
+        AuditAction::AccountRename,
+        AuditAction::AccountDisable,
+        AuditAction::AccountEnable,
+        AuditAction::InviteCreate,
+        AuditAction::PasswordChange,
+        AuditAction::PasswordResetLinkCreate,
+        AuditAction::PasswordReset,
+        AuditAction::TotpEnable,
+        AuditAction::TotpDisable,
+        AuditAction::TokenCreate,
+        AuditAction::TokenRevoke,
+    ];
+
+    /// Parses the name an action is serialized with, such as `article_update`.
+    pub fn from_name(name: &str) -> Option<AuditAction> {
+        use serde::de::IntoDeserializer;
+        let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+        AuditAction::deserialize(deserializer).ok()
+    }
+}
+
+/// A single line of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEvent {
+    /// The time of the event, in seconds since the unix epoch.
+    pub time: u64,
+    /// The user who performed the action.
+    pub user: User,
+    /// The address of the client that performed the action, if known.
+    pub address: Option<IpAddr>,
+    pub action: AuditAction,
+    /// The article path, or the username of the account, affected by the action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Any further details, such as why a login failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+/// Criteria for finding events in the audit log. Unset criteria match every event.
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    /// Matches events performed by the user with this name, ignoring case. `Anonymous` and
+    /// `Single User` match anonymous users and the single user.
+    pub user: Option<String>,
+    /// Matches events with any of these actions. Empty to match every action.
+    pub actions: Vec<AuditAction>,
+    /// Matches events with a target containing this text.
+    pub target: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        let user = self.user.as_deref().map(str::trim).filter(|user| !user.is_empty());
+        let target = self.target.as_deref().map(str::trim).filter(|target| !target.is_empty());
+        user.is_none_or(|user| event.user.to_string().eq_ignore_ascii_case(user))
+            && (self.actions.is_empty() || self.actions.contains(&event.action))
+            && target.is_none_or(|target| event.target.as_deref().is_some_and(|t| t.contains(target)))
+    }
+}
+
+/// An append-only log of edits and security events, stored as one JSON object per line.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Serializes appends, so that concurrent events can't interleave within a line.
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> AuditLog {
+        AuditLog { path, lock: Mutex::new(()) }
+    }
+
+    /// Appends the event to the log. Failures are logged rather than returned, since the action
+    /// being recorded has usually already happened.
+    pub async fn record(&self, event: AuditEvent) {
+        let mut line = serde_json::to_string(&event).expect("Audit event serialization should never fail.");
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let result = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await
+        }
+        .await;
+        if let Err(err) = result {
+            eprintln!("WARN: Failed to write to the audit log at {}: {err}", self.path.display());
+        }
+    }
+
+    /// Finds the most recent events matching the filter, newest first. The log is read backwards from
+    /// the end, so only as much of it is read as is needed to find the events.
+    pub async fn find(&self, filter: &AuditFilter, limit: usize) -> Result<Vec<AuditEvent>, std::io::Error> {
+        let mut file = match tokio::fs::File::open(&self.path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut events = Vec::new();
+        let mut position = file.metadata().await?.len();
+        // The start of a line that continues past the end of the chunk being read.
+        let mut remainder = Vec::new();
+        while position > 0 && events.len() < limit {
+            let start = position.saturating_sub(READ_CHUNK);
+            let mut chunk = vec![0; (position - start) as usize];
+            file.seek(SeekFrom::Start(start)).await?;
+            file.read_exact(&mut chunk).await?;
+            chunk.append(&mut remainder);
+            position = start;
+
+            // The first line may begin in the previous chunk, unless this is the start of the log.
+            let (head, lines) = match chunk.iter().position(|&byte| byte == b'\n') {
+                Some(end) if start > 0 => (&chunk[..end], &chunk[end + 1..]),
+                None if start > 0 => (&chunk[..], &[][..]),
+                _ => (&[][..], &chunk[..]),
+            };
+            for line in lines.rsplit(|&byte| byte == b'\n') {
+                // A partially written line shouldn't hide the rest of the log.
+                let Ok(event) = serde_json::from_slice::<AuditEvent>(line) else {
+                    continue;
+                };
+                if filter.matches(&event) && events.len() < limit {
+                    events.push(event);
+                }
+            }
+            remainder = head.to_vec();
+        }
+        Ok(events)
+    }
+}
+
+/// Records events to the audit log on behalf of the user and client making the current request.
+pub struct Auditor {
+    pub log: Arc<AuditLog>,
+    pub user: User,
+    pub address: Option<IpAddr>,
+}
+
+impl Auditor {
+    /// Records an action performed by the current user.
+    pub async fn record(&self, action: AuditAction, target: Option<&str>, details: Option<String>) {
+        self.record_as(self.user.clone(), action, target, details).await
+    }
+
+    /// Records an action performed by a different user than the current one, such as when logging in.
+    pub async fn record_as(&self, user: User, action: AuditAction, target: Option<&str>, details: Option<String>) {
+        self.log
+            .record(AuditEvent {
+                time: crate::unix_time(),
+                user,
+                address: self.address,
+                action,
+                target: target.map(str::to_owned),
+                details,
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::*;
+    use testdir::testdir;
+
+    #[tokio::test]
+    async fn record_and_find() {
+        let log = Arc::new(AuditLog::new(testdir!().join("audit.jsonl")));
+        let auditor = |user: User| Auditor {
+            log: log.clone(),
+            user,
+            address: Some([127, 0, 0, 1].into()),
+        };
+        auditor(User::Account("alex".into())).record(AuditAction::ArticleUpdate, Some("/notes"), None).await;
+        auditor(User::Anonymous).record(AuditAction::ArticleCreate, Some("/notes/log"), None).await;
+        auditor(User::Anonymous)
+            .record(AuditAction::LoginFailure, Some("morgan"), Some("Invalid password".to_owned()))
+            .await;
+
+        let all = log.find(&AuditFilter::default(), 10).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].action, AuditAction::LoginFailure);
+
+        let filter = AuditFilter {
+            target: Some("/notes".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(log.find(&filter, 10).await.unwrap().len(), 2);
+        assert_eq!(log.find(&filter, 1).await.unwrap()[0].action, AuditAction::ArticleCreate);
+
+        let filter = AuditFilter {
+            user: Some("ALEX".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(log.find(&filter, 10).await.unwrap().len(), 1);
+
+        let filter = AuditFilter {
+            actions: vec![AuditAction::ArticleCreate, AuditAction::ArticleUpdate],
+            ..Default::default()
+        };
+        assert_eq!(log.find(&filter, 10).await.unwrap().len(), 2);
+
+        assert_eq!(log.find(&AuditFilter::default(), 0).await.unwrap().len(), 0);
+        assert_eq!(AuditAction::from_name("login_failure"), Some(AuditAction::LoginFailure));
+        assert_eq!(AuditAction::from_name(""), None);
+    }
+
+    #[tokio::test]
+    async fn find_across_chunks() {
+        let path = testdir!().join("audit.jsonl");
+        let mut log = String::new();
+        for time in 0..5000 {
+            let event = AuditEvent {
+                time,
+                user: User::Account("alex".into()),
+                address: None,
+                action: if time == 0 { AuditAction::ArticleCreate } else { AuditAction::ArticleUpdate },
+                target: Some(format!("/notes/{time}")),
+                details: None,
+            };
+            log.push_str(&serde_json::to_string(&event).unwrap());
+            log.push('\n');
+        }
+        tokio::fs::write(&path, log).await.unwrap();
+        let log = AuditLog::new(path);
+
+        let newest = log.find(&AuditFilter::default(), 3).await.unwrap();
+        assert_eq!(newest.iter().map(|event| event.time).collect::<Vec<_>>(), vec![4999, 4998, 4997]);
+
+        // Lines split between chunks are still found.
+        let all = log.find(&AuditFilter::default(), 10000).await.unwrap();
+        assert_eq!(all.len(), 5000);
+        assert!(all.iter().rev().enumerate().all(|(time, event)| event.time == time as u64));
+
+        let filter = AuditFilter {
+            actions: vec![AuditAction::ArticleCreate],
+            ..Default::default()
+        };
+        assert_eq!(log.find(&filter, 10).await.unwrap()[0].time, 0);
+    }
+}
@@ -1,4 +1,5 @@
-use axum_extra::extract::SignedCookieJar;
+use crate::config::{CookieConfig, PasswordHashingConfig};
+use axum_extra::extract::{PrivateCookieJar, SignedCookieJar};
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
@@ -6,6 +7,12 @@ use std::fmt::{Display, Formatter};
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
 pub struct Username(String);
 
+impl Username {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl Display for Username {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.0)
@@ -28,24 +35,144 @@ impl From<String> for Username {
 pub struct Account {
     pub username: Username,
     password: String,
+    /// The base32 encoded TOTP secret, if the account has enrolled in two-factor authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    totp_secret: Option<String>,
+    /// The API tokens the account has created.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<ApiToken>,
+    /// Whether the account has been disabled by an administrator. Disabled accounts can't log in,
+    /// and any existing sessions are rejected.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    /// A random ID replaced whenever the password is set, but not when its hash is upgraded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    password_id: String,
 }
 
 impl Account {
-    pub fn new(username: Username, password: &str) -> Account {
-        let hash = hash_password(password);
+    pub fn new(username: Username, password: &str, hashing: &PasswordHashingConfig) -> Account {
+        let hash = hash_password(password, hashing);
 
         Account {
             username,
             password: hash.to_string(),
+            totp_secret: None,
+            tokens: Vec::new(),
+            disabled: false,
+            password_id: generate_random_token(),
+        }
+    }
+
+    /// Creates an account with an existing password hash, such as one imported from an htpasswd file.
+    pub fn with_hash(username: Username, hash: &str) -> Account {
+        Account {
+            username,
+            password: hash.to_owned(),
+            totp_secret: None,
+            tokens: Vec::new(),
+            disabled: false,
+            password_id: generate_random_token(),
         }
     }
 
+    /// Verifies the password of the account. Always fails if the account is disabled.
     pub fn verify_password(&self, password: &str) -> Result<(), ()> {
+        if self.disabled {
+            return Err(());
+        }
         verify_password(password, &self.password)
     }
 
-    pub fn set_password(&mut self, password: &str) {
-        self.password = hash_password(password);
+    pub fn set_password(&mut self, password: &str, hashing: &PasswordHashingConfig) {
+        self.password = hash_password(password, hashing);
+        self.password_id = generate_random_token();
+    }
+
+    /// Gets a fingerprint of the password, which changes whenever the password is set, but not when
+    /// its hash is upgraded. Safe to share, since it reveals nothing about the password or its hash.
+    pub fn password_fingerprint(&self) -> String {
+        self.password_id.clone()
+    }
+
+    /// Checks if the password hash uses outdated parameters, and should be replaced once the password
+    /// is next verified.
+    pub fn has_outdated_hash(&self, hashing: &PasswordHashingConfig) -> bool {
+        password_needs_rehash(&self.password, hashing)
+    }
+
+    /// Replaces the password hash with a new hash of the same password, if the current hash still
+    /// uses outdated parameters. Returns true if the hash was replaced.
+    pub fn replace_outdated_hash(&mut self, hash: String, hashing: &PasswordHashingConfig) -> bool {
+        if !password_needs_rehash(&self.password, hashing) {
+            return false;
+        }
+        self.password = hash;
+        true
+    }
+
+    pub fn totp_secret(&self) -> Option<&str> {
+        self.totp_secret.as_deref()
+    }
+
+    pub fn set_totp_secret(&mut self, secret: Option<String>) {
+        self.totp_secret = secret;
+    }
+}
+
+/// An invite that has been accepted, remembered until it expires so that it can't be accepted again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UsedInvite {
+    /// The nonce of the invite's token.
+    pub nonce: String,
+    /// The unix timestamp of when the invite expires.
+    pub expires: u64,
+}
+
+/// A named token used to authenticate scripts and other non-browser clients with an
+/// `Authorization: Bearer` header. Only a hash of the token is stored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiToken {
+    /// The name given to the token by its owner.
+    pub name: String,
+    /// The base64 encoded SHA-256 hash of the token.
+    hash: String,
+    /// The unix timestamp of when the token was created.
+    pub created: u64,
+}
+
+impl ApiToken {
+    /// The prefix of every token, making them easy to recognize.
+    const PREFIX: &str = "smolwik_";
+
+    /// Generates a new token with the specified name. Returns the token along with the secret token
+    /// string, which can't be recovered afterward.
+    pub fn generate(name: String) -> (ApiToken, String) {
+        use base64::prelude::*;
+        use rand_core::RngCore;
+
+        let mut bytes = vec![0u8; 32];
+        rand_core::OsRng.fill_bytes(&mut bytes);
+        let secret = format!("{}{}", Self::PREFIX, BASE64_URL_SAFE_NO_PAD.encode(&bytes));
+        let token = ApiToken {
+            name,
+            hash: Self::hash(&secret),
+            created: crate::unix_time(),
+        };
+        (token, secret)
+    }
+
+    pub fn verify(&self, secret: &str) -> bool {
+        secret.starts_with(Self::PREFIX) && Self::hash(secret) == self.hash
+    }
+
+    // Tokens have enough entropy that a fast hash is sufficient; a slow password hash would make
+    // every token-authenticated request expensive.
+    fn hash(secret: &str) -> String {
+        use base64::prelude::*;
+        use sha2::{Digest, Sha256};
+
+        BASE64_STANDARD.encode(Sha256::digest(secret.as_bytes()))
     }
 }
 
@@ -55,19 +182,26 @@ pub fn verify_password(password: &str, existing_hash: &str) -> Result<(), ()> {
         password_hash::{PasswordHash, PasswordVerifier},
     };
 
+    // Hashes imported from htpasswd files are replaced with Argon2 hashes the next time they verify.
+    if let Some(verified) = crate::htpasswd::verify(password, existing_hash) {
+        return if verified { Ok(()) } else { Err(()) };
+    }
+
+    // The parameters are read from the hash itself, so hashes using outdated parameters still verify.
     let existing_hash = PasswordHash::new(existing_hash).map_err(|_| ())?;
     let argon2 = Argon2::default();
     argon2.verify_password(password.as_bytes(), &existing_hash).map_err(|_| ())
 }
 
-pub fn hash_password(password: &str) -> String {
+pub fn hash_password(password: &str, hashing: &PasswordHashingConfig) -> String {
     use argon2::{
-        Argon2,
+        Algorithm, Argon2, Version,
         password_hash::{PasswordHasher, SaltString, rand_core::OsRng},
     };
 
+    let params = hashing.params().expect("Password hashing parameters are validated at startup.");
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
     let hash = argon2
         .hash_password(password.as_bytes(), &salt)
         .expect("Password hashing should be infallible.");
@@ -75,6 +209,31 @@ pub fn hash_password(password: &str) -> String {
     hash.to_string()
 }
 
+/// Checks if the hash was created with a different algorithm or parameters than currently configured,
+/// meaning it should be replaced the next time the password is known. Unparsable hashes never
+/// verify, so are never rehashed either.
+pub fn password_needs_rehash(existing_hash: &str, hashing: &PasswordHashingConfig) -> bool {
+    use argon2::{Algorithm, Params, Version, password_hash::PasswordHash};
+
+    if crate::htpasswd::is_htpasswd_hash(existing_hash) {
+        return true;
+    }
+    let Ok(existing_hash) = PasswordHash::new(existing_hash) else {
+        return false;
+    };
+    if existing_hash.algorithm != Algorithm::Argon2id.ident() || existing_hash.version != Some(Version::V0x13 as u32) {
+        return true;
+    }
+    match Params::try_from(&existing_hash) {
+        Ok(params) => {
+            params.m_cost() != hashing.memory_kib
+                || params.t_cost() != hashing.iterations
+                || params.p_cost() != hashing.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
 #[derive(Deserialize, Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum AuthenticationMode {
     /// No authentication at all, login is not possible.
@@ -84,6 +243,9 @@ pub enum AuthenticationMode {
     /// Multi-user authentication, with multiple accounts identified by a username, each with a
     /// unique password.
     Multi,
+    /// Authentication is performed by a reverse proxy, which passes the username of the
+    /// authenticated user in a header. Login is not possible through smolwik itself.
+    Proxy,
 }
 
 impl AuthenticationMode {
@@ -92,11 +254,13 @@ impl AuthenticationMode {
             AuthenticationMode::Anonymous => "anonymous",
             AuthenticationMode::Single => "single",
             AuthenticationMode::Multi => "multi",
+            AuthenticationMode::Proxy => "proxy",
         }
     }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
+#[allow(clippy::enum_variant_names)]
 pub enum User {
     /// No user is authenticated.
     Anonymous,
@@ -145,6 +309,13 @@ pub struct Session {
     pub id: Option<String>,
     /// The current user.
     pub user: User,
+    /// A login that has passed password verification, but is still awaiting verification of the
+    /// second factor. The session remains anonymous until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<PendingLogin>,
+    /// Whether the user asked to stay logged in after closing the browser.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub remember: bool,
 }
 
 impl Session {
@@ -152,6 +323,47 @@ impl Session {
         Session {
             id: Some(generate_random_token()),
             user,
+            pending: None,
+            remember: false,
+        }
+    }
+
+    /// Sets whether the session outlives the browser session, if enabled in the configuration.
+    pub fn with_remember(self, remember: bool) -> Session {
+        Session { remember, ..self }
+    }
+
+    /// Builds the session cookie. Remembered sessions are given a lifetime, so that the browser keeps
+    /// them after closing; others are discarded when the browser closes.
+    pub fn cookie(&self, config: &CookieConfig) -> Cookie<'static> {
+        let value = serde_json::to_string(self).expect("User must be serializable.");
+        let cookie = Cookie::build(("session", value))
+            .same_site(SameSite::Strict)
+            .secure(config.secure)
+            .http_only(config.http_only)
+            .path(config.path.clone());
+
+        match self.remember && config.remember_me_days > 0 {
+            true => cookie.max_age(time::Duration::days(config.remember_me_days.into())).build(),
+            false => cookie.build(),
+        }
+    }
+
+    /// Builds a cookie that removes the session cookie from the browser.
+    pub fn removal_cookie(config: &CookieConfig) -> Cookie<'static> {
+        Cookie::build("session").path(config.path.clone()).build()
+    }
+
+    /// Creates a new anonymous session with a pending login for the specified user.
+    pub fn pending(user: User) -> Session {
+        Session {
+            id: Some(generate_random_token()),
+            user: User::Anonymous,
+            pending: Some(PendingLogin {
+                user,
+                expires: crate::unix_time() + PendingLogin::LIFETIME_SECONDS,
+            }),
+            remember: false,
         }
     }
 }
@@ -161,26 +373,47 @@ impl Default for Session {
         Self {
             user: User::Anonymous,
             id: None,
+            pending: None,
+            remember: false,
         }
     }
 }
 
-impl From<Session> for Cookie<'_> {
-    fn from(value: Session) -> Self {
-        let value = serde_json::to_string(&value).expect("User must be serializable.");
+/// A login awaiting verification of a second authentication factor.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PendingLogin {
+    /// The user that will be authenticated once the second factor is verified.
+    pub user: User,
+    /// The unix timestamp after which the pending login is no longer valid.
+    pub expires: u64,
+}
+
+impl PendingLogin {
+    /// How long the second factor can be entered after the password has been verified.
+    const LIFETIME_SECONDS: u64 = 300;
 
-        Cookie::build(("session", value)).same_site(SameSite::Strict).build()
+    pub fn is_expired(&self) -> bool {
+        crate::unix_time() > self.expires
     }
 }
 
-impl From<SignedCookieJar> for Session {
-    fn from(value: SignedCookieJar) -> Self {
+impl Session {
+    /// Reads the session from a cookie that was only signed rather than encrypted, as issued by
+    /// earlier versions. [None] if there's no such cookie, such as when it's already encrypted.
+    pub fn from_legacy_cookie(jar: &SignedCookieJar) -> Option<Session> {
+        let cookie = jar.get("session")?;
+        serde_json::from_str(cookie.value()).ok()
+    }
+}
+
+impl From<PrivateCookieJar> for Session {
+    fn from(value: PrivateCookieJar) -> Self {
         Self::from(&value)
     }
 }
 
-impl From<&SignedCookieJar> for Session {
-    fn from(value: &SignedCookieJar) -> Self {
+impl From<&PrivateCookieJar> for Session {
+    fn from(value: &PrivateCookieJar) -> Self {
         match value.get("session") {
             None => Self::default(),
             Some(cookie) => serde_json::from_str(cookie.value()).unwrap_or(Self::default()),
@@ -206,6 +439,33 @@ impl Access {
             Access::Accounts(_) => "accounts",
         }
     }
+
+    /// Checks if the list of usernames contains the specified username.
+    pub fn references(&self, username: &Username) -> bool {
+        match self {
+            Access::Accounts(allowed) => allowed.contains(username),
+            _ => false,
+        }
+    }
+
+    /// Replaces the specified username in the list of usernames, or removes it if there's no
+    /// replacement. Returns true if the list was changed.
+    pub fn replace_username(&mut self, old: &Username, new: Option<&Username>) -> bool {
+        let Access::Accounts(allowed) = self else {
+            return false;
+        };
+        if !allowed.contains(old) {
+            return false;
+        }
+
+        allowed.retain(|username| username != old);
+        if let Some(new) = new
+            && !allowed.contains(new)
+        {
+            allowed.push(new.clone());
+        }
+        true
+    }
 }
 
 /// The result of an access check.
@@ -223,7 +483,7 @@ fn generate_random_token() -> String {
     use base64::prelude::*;
     use rand_core::RngCore;
     let mut bytes = vec![0u8; 64];
-    rand_core::OsRng::default().fill_bytes(&mut bytes);
+    rand_core::OsRng.fill_bytes(&mut bytes);
     BASE64_STANDARD.encode(&bytes)
 }
 
@@ -232,10 +492,38 @@ mod tests {
     use std::assert_matches;
     use crate::auth::*;
 
+    #[test]
+    fn session_cookie_attributes() {
+        let config = CookieConfig::default();
+        let cookie = Session::new(User::SingleUser).cookie(&config);
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.max_age(), None);
+
+        let cookie = Session::new(User::SingleUser).with_remember(true).cookie(&config);
+        assert_eq!(cookie.max_age(), Some(time::Duration::days(30)));
+
+        let config = CookieConfig {
+            remember_me_days: 0,
+            ..Default::default()
+        };
+        let cookie = Session::new(User::SingleUser).with_remember(true).cookie(&config);
+        assert_eq!(cookie.max_age(), None);
+    }
+
+    #[test]
+    fn api_token_verify() {
+        let (token, secret) = ApiToken::generate("script".to_owned());
+        assert!(token.verify(&secret));
+        assert!(!token.verify("smolwik_not_the_token"));
+        assert!(!token.verify(""));
+    }
+
     #[test]
     fn create_account_verify_password() -> Result<(), ()> {
         let password = "password";
-        let account = Account::new("username".into(), password);
+        let account = Account::new("username".into(), password, &PasswordHashingConfig::default());
         assert_matches!(account.verify_password(password), Ok(()));
         assert_matches!(account.verify_password("not_password"), Err(()));
         Ok(())
@@ -245,13 +533,62 @@ mod tests {
     fn set_password_verify_password() -> Result<(), ()> {
         let old_password = "old_password";
         let new_password = "new_password";
-        let mut account = Account::new("username".into(), old_password);
-        account.set_password(new_password);
+        let hashing = PasswordHashingConfig::default();
+        let mut account = Account::new("username".into(), old_password, &hashing);
+        account.set_password(new_password, &hashing);
         assert_matches!(account.verify_password(new_password), Ok(()));
         assert_matches!(account.verify_password(old_password), Err(()));
         Ok(())
     }
 
+    #[test]
+    fn rehash_outdated_password() {
+        let old_hashing = PasswordHashingConfig {
+            memory_kib: 8192,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let hashing = PasswordHashingConfig::default();
+        let mut account = Account::new("username".into(), "password", &old_hashing);
+        let fingerprint = account.password_fingerprint();
+        assert!(account.has_outdated_hash(&hashing));
+        assert!(account.replace_outdated_hash(hash_password("password", &hashing), &hashing));
+        assert!(!account.has_outdated_hash(&hashing));
+        assert!(!account.replace_outdated_hash(hash_password("password", &hashing), &hashing));
+        assert_matches!(account.verify_password("password"), Ok(()));
+
+        // Reset links stay valid while the hash is upgraded, but not once the password changes.
+        assert_eq!(account.password_fingerprint(), fingerprint);
+        account.set_password("new password", &hashing);
+        assert_ne!(account.password_fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn disabled_account_verify_password() {
+        let mut account = Account::new("username".into(), "password", &PasswordHashingConfig::default());
+        account.disabled = true;
+        assert_matches!(account.verify_password("password"), Err(()));
+    }
+
+    #[test]
+    fn access_replace_username() {
+        let mut access = Access::Accounts(vec!["alex".into(), "morgan".into()]);
+        assert!(access.replace_username(&"alex".into(), Some(&"sam".into())));
+        assert!(!access.references(&"alex".into()));
+        assert!(access.references(&"sam".into()));
+
+        // Renaming to a username already in the list doesn't duplicate it.
+        assert!(access.replace_username(&"sam".into(), Some(&"morgan".into())));
+        assert_matches!(&access, Access::Accounts(allowed) if allowed == &vec![Username::from("morgan")]);
+
+        assert!(access.replace_username(&"morgan".into(), None));
+        assert_matches!(&access, Access::Accounts(allowed) if allowed.is_empty());
+        assert!(!access.replace_username(&"morgan".into(), None));
+
+        let mut access = Access::Authenticated;
+        assert!(!access.replace_username(&"alex".into(), None));
+    }
+
     #[test]
     fn user_authorization() -> Result<(), ()> {
         use Authorization::*;
@@ -273,6 +610,8 @@ mod tests {
         assert_matches!(user.check_authorization(&Access::Authenticated), Authorized);
         assert_matches!(user.check_authorization(&Access::Accounts(vec!["alex".into()])), Authorized);
         assert_matches!(user.check_authorization(&Access::Accounts(vec!["morgan".into()])), Unauthorized);
+        // Access lists match usernames exactly, since logins resolve to the account's own username.
+        assert_matches!(user.check_authorization(&Access::Accounts(vec!["Alex".into()])), Unauthorized);
         Ok(())
     }
 }
@@ -0,0 +1,413 @@
+use crate::audit::{AuditAction, AuditEvent};
+use crate::auth::{AuthenticationMode, Session, User, Username, verify_password};
+use crate::config::{AccountConfig, AccountPolicyConfig, ProxyAuthConfig};
+use crate::policy;
+use crate::extractors::ClientAddress;
+use crate::throttle::{LoginAttempt, LoginTarget};
+use crate::responses::AuthenticationRequired;
+use crate::{AppState, ErrorResponse, render_error};
+use axum::extract::State;
+use axum::middleware::Next;
+use axum_core::body::Body;
+use axum_core::response::{IntoResponse, Response};
+use axum_extra::extract::{PrivateCookieJar, SignedCookieJar};
+use http::{HeaderMap, HeaderValue, Request, header};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Marks a request as authenticated with an API token instead of the session cookie. Such requests
+/// can't be forged by another site, so aren't subject to CSRF checks.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenAuthenticated;
+
+/// Authenticates requests carrying credentials other than the session cookie. On success, the
+/// resulting [Session] is added to the request extensions, taking precedence over the cookie.
+pub async fn authentication_middleware(
+    State(state): State<AppState>,
+    ClientAddress(address): ClientAddress,
+    jar: PrivateCookieJar,
+    legacy_jar: SignedCookieJar,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    if let Some(token) = bearer_token(request.headers()) {
+        if !allows_tokens(request.uri().path()) {
+            return render_error(&state, &Session::default(), ErrorResponse::token_not_allowed());
+        }
+        let user = match authenticate_token(&state, token).await {
+            Ok(user) => user,
+            Err(err) => return render_error(&state, &Session::default(), err),
+        };
+
+        let extensions = request.extensions_mut();
+        extensions.insert(Session {
+            id: None,
+            user,
+            pending: None,
+            remember: false,
+        });
+        extensions.insert(TokenAuthenticated);
+        return next.run(request).await;
+    }
+
+    // Basic credentials are ignored in the other modes, where there's no password to check them against.
+    let basic_available = matches!(state.config.auth_mode, AuthenticationMode::Single | AuthenticationMode::Multi);
+    if basic_available && let Some((username, password)) = basic_credentials(request.headers()) {
+        let user = match authenticate_basic(&state, address, username, &password).await {
+            Ok(user) => user,
+            Err(err) => return with_basic_challenge(&state, render_error(&state, &Session::default(), err)),
+        };
+
+        // Unlike API tokens, browsers attach Basic credentials to cross-site requests automatically,
+        // so forms remain subject to CSRF checks.
+        request.extensions_mut().insert(Session {
+            id: None,
+            user,
+            pending: None,
+            remember: false,
+        });
+        return next.run(request).await;
+    }
+
+    if state.config.auth_mode == AuthenticationMode::Proxy {
+        let mut user = proxy_user(&state.config.proxy_auth, request.headers(), address);
+        // Users authenticated by the proxy don't need an account, but can still be disabled by one.
+        match account_status(&state, &user).await {
+            Ok(AccountStatus::Disabled) => user = User::Anonymous,
+            Ok(_) => {}
+            Err(err) => return render_error(&state, &Session::default(), err),
+        }
+        // The session cookie is still used to protect forms against CSRF, so a session ID must
+        // exist even though the user never logs in.
+        let (cookie_session, migrated_jar) = cookie_session(&state, &jar, &legacy_jar);
+        let (id, jar) = match cookie_session.id {
+            Some(id) => (id, migrated_jar),
+            None => {
+                let session = Session::new(User::Anonymous);
+                let id = session.id.clone().expect("New sessions always have an ID.");
+                (id, Some(jar.add(session.cookie(&state.config.cookies))))
+            }
+        };
+
+        request.extensions_mut().insert(Session {
+            id: Some(id),
+            user,
+            pending: None,
+            remember: false,
+        });
+        let response = next.run(request).await;
+        return match jar {
+            Some(jar) => (jar, response).into_response(),
+            None => response,
+        };
+    }
+
+    // Sessions outlive changes to accounts.toml, so an account may have been disabled or deleted
+    // since the user logged in. Such sessions continue as anonymous.
+    let (session, migrated_jar) = cookie_session(&state, &jar, &legacy_jar);
+    let session = match account_status(&state, &session.user).await {
+        Ok(AccountStatus::Active) => session,
+        Ok(AccountStatus::Disabled | AccountStatus::Missing) => Session {
+            user: User::Anonymous,
+            ..session
+        },
+        Err(err) => return render_error(&state, &Session::default(), err),
+    };
+    request.extensions_mut().insert(session);
+
+    // Browsers show their own login prompt when challenged, which would bypass the login page, so
+    // only other clients are challenged.
+    let is_browser = accepts_html(request.headers());
+    let mut response = next.run(request).await;
+    // A session cookie set by the handler, such as when logging in, replaces the migrated one.
+    if let Some(jar) = migrated_jar
+        && !sets_session_cookie(&response)
+    {
+        response = (jar, response).into_response();
+    }
+    match is_browser {
+        true => response,
+        false => with_basic_challenge(&state, response),
+    }
+}
+
+/// Reads the session from the encrypted session cookie. Earlier versions only signed the cookie, so
+/// such sessions are carried over, returning the jar with the cookie replaced by an encrypted one.
+fn cookie_session(
+    state: &AppState,
+    jar: &PrivateCookieJar,
+    legacy_jar: &SignedCookieJar,
+) -> (Session, Option<PrivateCookieJar>) {
+    if jar.get("session").is_some() {
+        return (Session::from(jar), None);
+    }
+    match Session::from_legacy_cookie(legacy_jar) {
+        Some(session) => {
+            let jar = jar.clone().add(session.cookie(&state.config.cookies));
+            (session, Some(jar))
+        }
+        None => (Session::default(), None),
+    }
+}
+
+fn sets_session_cookie(response: &Response) -> bool {
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.starts_with("session="))
+}
+
+/// Verifies the password of the specified user under the specified authentication mode. Returns the
+/// authenticated user, or [None] if the credentials are invalid. The username is ignored in
+/// single-user mode.
+pub fn verify_credentials(
+    account_config: &AccountConfig,
+    auth_mode: AuthenticationMode,
+    username: Option<&Username>,
+    password: &str,
+) -> Option<User> {
+    match auth_mode {
+        AuthenticationMode::Anonymous | AuthenticationMode::Proxy => None,
+        AuthenticationMode::Single => account_config
+            .single_password
+            .as_ref()
+            .is_some_and(|hash| verify_password(password, hash).is_ok())
+            .then_some(User::SingleUser),
+        AuthenticationMode::Multi => {
+            let acc = account_config.find_by_username(username?)?;
+            acc.verify_password(password).ok().map(|_| User::Account(acc.username.clone()))
+        }
+    }
+}
+
+/// Gets the username of the account a login as `username` is for, ignoring case if the policy does.
+/// Names without an account are returned as given, so that failures against them are still throttled.
+pub fn login_username(policy: &AccountPolicyConfig, account_config: &AccountConfig, username: &Username) -> Username {
+    account_config
+        .accounts
+        .iter()
+        .map(|acc| &acc.username)
+        .find(|existing| policy::is_same_username(policy, existing, username))
+        .unwrap_or(username)
+        .clone()
+}
+
+/// Saves the new password hash of a user who has just fully authenticated, from
+/// [AccountConfig::rehashed_password], so that changes to the hashing parameters take effect without
+/// resetting passwords. Failing to save the new hash doesn't affect the login, since the old hash still works.
+pub async fn save_rehashed_password(state: &AppState, account_config: &mut AccountConfig, user: &User, hash: Option<String>) {
+    let Some(hash) = hash else {
+        return;
+    };
+    if !account_config.replace_outdated_hash(user, hash, &state.config.password_hashing) {
+        return;
+    }
+    if let Err(err) = state.accounts.save(account_config).await {
+        eprintln!("WARN: Failed to save the rehashed password of {user}: {}", err.details);
+    }
+}
+
+/// New password hashes from logins waiting for their second factor, by the ID of the pending session.
+/// Kept on the server, so that the hash is never sent to the client in the session cookie.
+#[derive(Default)]
+pub struct PendingRehashes {
+    /// The hash, and when the pending login expires.
+    hashes: Mutex<HashMap<String, (String, u64)>>,
+}
+
+impl PendingRehashes {
+    /// Keeps the new hash until the pending login in the session succeeds or expires.
+    pub fn insert(&self, session: &Session, hash: String) {
+        let (Some(id), Some(pending)) = (&session.id, &session.pending) else {
+            return;
+        };
+        let mut hashes = self.hashes.lock().expect("Pending rehashes lock poisoned.");
+        let now = crate::unix_time();
+        hashes.retain(|_, (_, expires)| *expires >= now);
+        hashes.insert(id.clone(), (hash, pending.expires));
+    }
+
+    /// Removes the new hash kept for the session with the ID, returning it if the pending login hasn't
+    /// expired.
+    pub fn take(&self, session_id: &str) -> Option<String> {
+        let (hash, expires) = self.hashes.lock().expect("Pending rehashes lock poisoned.").remove(session_id)?;
+        (expires >= crate::unix_time()).then_some(hash)
+    }
+}
+
+/// The status of the account behind an authenticated user.
+enum AccountStatus {
+    /// The account exists and is enabled, or the user isn't backed by an account.
+    Active,
+    /// The account exists, but has been disabled.
+    Disabled,
+    /// The account doesn't exist.
+    Missing,
+}
+
+async fn account_status(state: &AppState, user: &User) -> Result<AccountStatus, ErrorResponse> {
+    let User::Account(username) = user else {
+        return Ok(AccountStatus::Active);
+    };
+
+    let account_config = state.accounts.load().await?;
+    Ok(match account_config.find_by_username(username) {
+        None => AccountStatus::Missing,
+        Some(acc) if acc.disabled => AccountStatus::Disabled,
+        Some(_) => AccountStatus::Active,
+    })
+}
+
+/// Gets the user authenticated by the reverse proxy. The header is only trusted when the request
+/// comes directly from one of the trusted proxies, otherwise anyone could set it.
+fn proxy_user(config: &ProxyAuthConfig, headers: &HeaderMap, address: Option<IpAddr>) -> User {
+    let username = match headers.get(config.header.as_str()).map(|value| value.to_str()) {
+        None => return User::Anonymous,
+        Some(Ok(username)) if !username.trim().is_empty() => username.trim(),
+        Some(_) => return User::Anonymous,
+    };
+
+    match address {
+        Some(address) if config.trusted_proxies.contains(&address) => User::Account(username.into()),
+        _ => {
+            let address = address.map_or("an unknown address".to_owned(), |address| address.to_string());
+            eprintln!("WARN: Ignored `{}` header from untrusted address {address}.", config.header);
+            User::Anonymous
+        }
+    }
+}
+
+async fn authenticate_token(state: &AppState, token: &str) -> Result<User, ErrorResponse> {
+    let account_config = state.accounts.load().await?;
+    match account_config.find_token_owner(token, state.config.auth_mode) {
+        Some(user) => Ok(user),
+        None => Err(ErrorResponse::invalid_token()),
+    }
+}
+
+/// Checks if API tokens may be used for the path. Tokens skip CSRF checks and are meant for scripts,
+/// so they're limited to articles, keeping them away from account and administration pages.
+fn allows_tokens(path: &str) -> bool {
+    !path.starts_with("/special:") || path == "/special:create"
+}
+
+/// Gets the token from an `Authorization: Bearer` header, if present.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+async fn authenticate_basic(
+    state: &AppState,
+    address: Option<IpAddr>,
+    username: Username,
+    password: &str,
+) -> Result<User, ErrorResponse> {
+    let mut account_config = state.accounts.load().await?;
+    let username = login_username(&state.config.account_policy, &account_config, &username);
+    let target = LoginTarget::for_login(state.config.auth_mode, Some(&username));
+    let attempt = LoginAttempt::new(target, address);
+    state.throttle.begin_attempt(&attempt)?;
+
+    let user = match verify_credentials(&account_config, state.config.auth_mode, Some(&username), password) {
+        Some(user) => user,
+        None => {
+            // Successful Basic logins aren't recorded, since the credentials are sent with every request.
+            state
+                .audit
+                .record(AuditEvent {
+                    time: crate::unix_time(),
+                    user: User::Anonymous,
+                    address,
+                    action: AuditAction::LoginFailure,
+                    target: Some(username.to_string()),
+                    details: Some("Invalid Basic credentials".to_owned()),
+                })
+                .await;
+            return Err(ErrorResponse::invalid_credentials());
+        }
+    };
+
+    // There's no way to provide a second factor with Basic authentication, so allowing it would
+    // bypass two-factor authentication entirely.
+    if account_config.totp_secret(&user).is_some() {
+        state.throttle.record_partial_attempt(&attempt);
+        return Err(ErrorResponse::basic_auth_unavailable());
+    }
+
+    state.throttle.record_successful_attempt(&attempt);
+    let hash = account_config.rehashed_password(&user, password, &state.config.password_hashing);
+    save_rehashed_password(state, &mut account_config, &user, hash).await;
+    Ok(user)
+}
+
+/// Gets the username and password from an `Authorization: Basic` header, if present and well-formed.
+fn basic_credentials(headers: &HeaderMap) -> Option<(Username, String)> {
+    use base64::prelude::*;
+
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.into(), password.to_owned()))
+}
+
+/// Checks if the request was likely made by a browser, which always accept HTML.
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
+
+/// Adds a `WWW-Authenticate` challenge to responses asking the user to authenticate, prompting the
+/// client to retry with Basic credentials. Only done in the modes where Basic authentication is available.
+fn with_basic_challenge(state: &AppState, mut response: Response) -> Response {
+    let available = matches!(state.config.auth_mode, AuthenticationMode::Single | AuthenticationMode::Multi);
+    if available && response.extensions().get::<AuthenticationRequired>().is_some() {
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="smolwik", charset="UTF-8""#),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::PendingLogin;
+    use crate::authentication::*;
+
+    #[test]
+    fn pending_rehashes() {
+        let rehashes = PendingRehashes::default();
+        let session = Session::pending(User::SingleUser);
+        let id = session.id.clone().unwrap();
+        rehashes.insert(&session, "new hash".to_owned());
+        assert!(!serde_json::to_string(&session).unwrap().contains("new hash"));
+
+        assert_eq!(rehashes.take("another session"), None);
+        assert_eq!(rehashes.take(&id), Some("new hash".to_owned()));
+        assert_eq!(rehashes.take(&id), None);
+
+        // Hashes aren't kept past the pending login's expiry.
+        let expired = Session {
+            pending: Some(PendingLogin {
+                user: User::SingleUser,
+                expires: 0,
+            }),
+            ..Session::pending(User::SingleUser)
+        };
+        rehashes.insert(&expired, "new hash".to_owned());
+        assert_eq!(rehashes.take(expired.id.as_deref().unwrap()), None);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::article::RawArticle;
+use crate::auth::User;
+use crate::markdown::{self, WikiLink, WikiLinks};
+use crate::references;
+use crate::routes::articles::get_paths;
+use crate::titles::TitleIndex;
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// An article linking to another.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Backlink {
+    pub url: String,
+    pub title: String,
+}
+
+/// The links in an article.
+struct ArticleLinks {
+    /// The URLs of the articles linked to by ordinary links, which don't depend on other articles.
+    direct: HashSet<String>,
+    /// The destinations of the wiki links, which are resolved again whenever a title changes.
+    wiki: Vec<String>,
+    /// The URLs of every linked article, with the wiki links as last resolved.
+    resolved: HashSet<String>,
+}
+
+/// An index of the links between articles, used to find the articles linking to each one. Built when
+/// the server starts, and updated whenever an article is saved.
+pub struct BacklinkIndex {
+    articles: PathBuf,
+    /// The links in each article, by the URL of the linking article.
+    links: RwLock<HashMap<String, ArticleLinks>>,
+}
+
+impl BacklinkIndex {
+    /// Builds the index by reading every article under the article root, adding each one to `titles`.
+    pub async fn build(articles: &Path, titles: &TitleIndex) -> BacklinkIndex {
+        let mut found = match references::find_articles(articles).await {
+            Ok(found) => found,
+            Err(err) => {
+                eprintln!("WARN: Failed to find articles while indexing links: {err}");
+                Vec::new()
+            }
+        };
+        found.sort_by(|first, second| first.1.cmp(&second.1));
+
+        // Wiki links can refer to titles, so every article is read before any links are resolved.
+        let mut links = HashMap::with_capacity(found.len());
+        for (filepath, url) in found {
+            match RawArticle::read_from_path(&filepath, &url).await {
+                Ok(article) => {
+                    titles.insert(&url, &article.metadata);
+                    links.insert(url.clone(), find_links(articles, &url, &article.markdown));
+                }
+                Err(err) => eprintln!("WARN: Skipped article while indexing links: {err}"),
+            }
+        }
+
+        let index = BacklinkIndex {
+            articles: articles.to_path_buf(),
+            links: RwLock::new(links),
+        };
+        index.resolve_all(titles);
+        index
+    }
+
+    /// Indexes an article that was just saved, adding it to `titles`. Wiki links in every article
+    /// are resolved again if the article is new or its title changed, since they may now lead to it.
+    pub fn update(&self, titles: &TitleIndex, url: &str, article: &RawArticle) {
+        let previous = titles.insert(url, &article.metadata);
+        let mut links = find_links(&self.articles, url, &article.markdown);
+        links.resolved = self.resolve(titles, url, &links);
+        self.links.write().expect("Backlink index lock poisoned.").insert(url.to_owned(), links);
+
+        if previous.as_ref() != Some(&article.metadata.title) {
+            self.resolve_all(titles);
+        }
+    }
+
+    /// Finds the articles linking to the article at the specified URL that the user may view, sorted by title.
+    pub fn find(&self, titles: &TitleIndex, url: &str, user: &User) -> Vec<Backlink> {
+        let links = self.links.read().expect("Backlink index lock poisoned.");
+        let mut backlinks: Vec<_> = links
+            .iter()
+            .filter(|(_, links)| links.resolved.contains(url))
+            .filter_map(|(source, _)| titles.get(source))
+            .filter(|source| source.is_visible_to(Some(user)))
+            .map(|source| Backlink {
+                url: source.url,
+                title: source.title,
+            })
+            .collect();
+        backlinks.sort_by_key(|backlink| (backlink.title.to_lowercase(), backlink.url.clone()));
+        backlinks
+    }
+
+    /// Resolves the links of every article again.
+    fn resolve_all(&self, titles: &TitleIndex) {
+        let mut links = self.links.write().expect("Backlink index lock poisoned.");
+        for (url, article) in links.iter_mut() {
+            article.resolved = self.resolve(titles, url, article);
+        }
+    }
+
+    /// Resolves the links of the article at `url` to the URLs of the articles they lead to. Wiki links
+    /// are resolved regardless of access, and links to the article itself aren't included.
+    fn resolve(&self, titles: &TitleIndex, url: &str, links: &ArticleLinks) -> HashSet<String> {
+        let wiki_links = WikiLinks::resolve_targets(titles, None, url, links.wiki.iter().map(String::as_str));
+        let wiki = links.wiki.iter().filter_map(|dest_url| match wiki_links.get(dest_url) {
+            Some(WikiLink::Article(target)) => resolve_link(&self.articles, markdown::directory(url), target),
+            Some(WikiLink::Missing { path, .. }) => resolve_link(&self.articles, markdown::directory(url), &format!("/{path}")),
+            None => None,
+        });
+        links.direct.iter().cloned().chain(wiki).filter(|target| target != url).collect()
+    }
+}
+
+/// Finds the links in the markdown of the article at `url`. Ordinary links are resolved to the URLs
+/// of the articles they lead to, including articles that don't exist yet, while wiki links are kept
+/// to be resolved later.
+fn find_links(articles: &Path, url: &str, markdown: &str) -> ArticleLinks {
+    let mut direct = HashSet::new();
+    for event in Parser::new_ext(markdown, Options::all()) {
+        let Event::Start(Tag::Link {
+            link_type, dest_url, ..
+        }) = event
+        else {
+            continue;
+        };
+        if matches!(link_type, LinkType::WikiLink { .. } | LinkType::Email) {
+            continue;
+        }
+        if let Some(target) = resolve_link(articles, markdown::directory(url), &dest_url) {
+            direct.insert(target);
+        }
+    }
+    ArticleLinks {
+        direct,
+        wiki: markdown::wiki_link_targets(markdown).into_iter().map(|target| target.to_string()).collect(),
+        resolved: HashSet::new(),
+    }
+}
+
+/// Resolves a link to the URL of the article it refers to. [None] for external links, and links
+/// to special pages or assets.
+fn resolve_link(articles: &Path, directory: &str, link: &str) -> Option<String> {
+    if link.contains("://") || link.starts_with("//") || link.starts_with("mailto:") {
+        return None;
+    }
+    let path = link.split(['#', '?']).next().unwrap_or_default();
+    if path.is_empty() {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let joined = match path.strip_prefix('/') {
+        Some(absolute) => absolute.to_owned(),
+        None => format!("{directory}/{path}"),
+    };
+    for segment in joined.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    if segments.first().is_some_and(|first| *first == "assets") {
+        return None;
+    }
+    // Special pages are rejected here too.
+    get_paths(articles, &segments.join("/")).map(|paths| paths.url)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Access;
+    use crate::backlinks::*;
+    use crate::metadata::Metadata;
+    use testdir::testdir;
+
+    async fn write(articles: &Path, url: &str, title: &str, view_access: Access, markdown: &str) {
+        let article = RawArticle {
+            metadata: Metadata {
+                title: title.to_owned(),
+                edit_access: Access::Authenticated,
+                view_access,
+            },
+            markdown: markdown.to_owned(),
+        };
+        let filepath = articles.join(&url[1..]).with_extension("md");
+        std::fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+        article.write_to_path(&filepath, url).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn find_backlinks() {
+        let articles = testdir!();
+        write(&articles, "/house/index", "House", Access::Anonymous, "[[Dishwasher]] [manual](dishwasher#manual)").await;
+        write(&articles, "/house/dishwasher", "Dishwasher", Access::Anonymous, "[Back](../house/)").await;
+        write(&articles, "/private", "Private", Access::Authenticated, "[[/house/dishwasher]]").await;
+        write(&articles, "/external", "External", Access::Anonymous, "[x](https://example.com) [y](/special:tree)").await;
+
+        let titles = TitleIndex::default();
+        let index = BacklinkIndex::build(&articles, &titles).await;
+        let backlink = |url: &str, title: &str| Backlink {
+            url: url.to_owned(),
+            title: title.to_owned(),
+        };
+        assert_eq!(index.find(&titles, "/house/dishwasher", &User::Anonymous), vec![backlink("/house/index", "House")]);
+        assert_eq!(
+            index.find(&titles, "/house/dishwasher", &User::SingleUser),
+            vec![backlink("/house/index", "House"), backlink("/private", "Private")]
+        );
+        assert_eq!(index.find(&titles, "/house/index", &User::Anonymous), vec![backlink("/house/dishwasher", "Dishwasher")]);
+
+        // Links to articles that don't exist yet are found once they're created.
+        let article = |title: &str, markdown: &str| RawArticle {
+            metadata: Metadata {
+                title: title.to_owned(),
+                ..Default::default()
+            },
+            markdown: markdown.to_owned(),
+        };
+        index.update(&titles, "/external", &article("External", "[[Freezer]] [[Cold Storage]]"));
+        assert_eq!(index.find(&titles, "/freezer", &User::Anonymous), vec![backlink("/external", "External")]);
+
+        // Links to titles follow the titles as they change.
+        index.update(&titles, "/garage", &article("Cold Storage", ""));
+        assert_eq!(index.find(&titles, "/garage", &User::Anonymous), vec![backlink("/external", "External")]);
+        index.update(&titles, "/garage", &article("Garage", ""));
+        assert!(index.find(&titles, "/garage", &User::Anonymous).is_empty());
+    }
+}
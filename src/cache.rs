@@ -0,0 +1,194 @@
+use crate::auth::User;
+use crate::markdown::RenderedMarkdown;
+use axum::response::Response;
+use base64::prelude::*;
+use http::{HeaderMap, HeaderValue, header};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use tera::Context;
+
+/// The modification time and size of a file, which change whenever it's written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: SystemTime,
+    pub size: u64,
+}
+
+impl FileStamp {
+    /// Stamps the file at the path. [None] if it doesn't exist.
+    pub async fn of(path: &Path) -> Option<FileStamp> {
+        let metadata = tokio::fs::metadata(path).await.ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+/// A file a render was made from, stamped before it was read.
+pub type Source = (PathBuf, Option<FileStamp>);
+
+struct CachedRender {
+    sources: Vec<Source>,
+    /// The user it was rendered for, if it depends on which articles they may view.
+    viewer: Option<User>,
+    rendered: RenderedMarkdown,
+}
+
+/// The rendered markdown of each article, reused until any of the files it was rendered from
+/// change. Cleared whenever an article is saved, since links to it from other articles may change.
+#[derive(Default)]
+pub struct RenderCache {
+    renders: RwLock<HashMap<String, CachedRender>>,
+}
+
+impl RenderCache {
+    /// Gets the render of the article at `url` for the user, if none of its sources have changed.
+    /// Also returns when the newest of its sources was last modified.
+    pub async fn get(&self, url: &str, user: &User) -> Option<(RenderedMarkdown, SystemTime)> {
+        let (sources, rendered) = {
+            let renders = self.renders.read().expect("Render cache lock poisoned.");
+            let cached = renders.get(url)?;
+            if cached.viewer.as_ref().is_some_and(|viewer| viewer != user) {
+                return None;
+            }
+            (cached.sources.clone(), cached.rendered.clone())
+        };
+        for (path, stamp) in &sources {
+            if FileStamp::of(path).await != *stamp {
+                return None;
+            }
+        }
+        Some((rendered, last_modified(&sources)))
+    }
+
+    /// Caches the render of the article at `url`. Returns when the newest of its sources was last modified.
+    pub fn insert(&self, url: &str, user: &User, mut sources: Vec<Source>, rendered: &RenderedMarkdown) -> SystemTime {
+        // Only included articles, and links to articles not everyone may see, depend on the viewer's access.
+        let viewer = (!rendered.included.is_empty() || rendered.restricted_links).then(|| user.clone());
+        sources.extend(rendered.included.iter().cloned());
+        let modified = last_modified(&sources);
+        let cached = CachedRender {
+            sources,
+            viewer,
+            rendered: rendered.clone(),
+        };
+        self.renders.write().expect("Render cache lock poisoned.").insert(url.to_owned(), cached);
+        modified
+    }
+
+    pub fn clear(&self) {
+        self.renders.write().expect("Render cache lock poisoned.").clear();
+    }
+}
+
+fn last_modified(sources: &[Source]) -> SystemTime {
+    sources.iter().filter_map(|(_, stamp)| stamp.map(|stamp| stamp.modified)).max().unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Computes the ETag of a page from the template and the full context it's rendered with, which
+/// include who is logged in, the session ID in its forms and everything else shown besides the
+/// article. Doesn't need the page to be rendered, so unchanged pages aren't rendered at all.
+pub fn page_etag(template: &str, context: &Context) -> String {
+    let context = serde_json::to_vec(&context.clone().into_json()).expect("Contexts are always serializable.");
+    let digest = Sha256::new().chain_update(template).chain_update([0]).chain_update(context).finalize();
+    format!("\"{}\"", BASE64_URL_SAFE_NO_PAD.encode(&digest[..12]))
+}
+
+/// Checks if the request's `If-None-Match` header shows the client already has the page with the
+/// ETag. `If-Modified-Since` isn't used, since pages change for more reasons than their sources
+/// being modified, such as the articles linking to them.
+pub fn is_not_modified(etag: &str, request: &HeaderMap) -> bool {
+    request
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|tags| tags.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Adds the headers letting clients make conditional requests for a page to its response.
+pub fn with_validators(mut response: Response, etag: &str, last_modified: SystemTime) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(header::ETAG, HeaderValue::from_str(etag).expect("ETag is always a valid header value."));
+    let last_modified = httpdate::fmt_http_date(last_modified);
+    headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&last_modified).expect("Dates are valid header values."));
+    // Browsers must check the page is unchanged before reusing it, and shouldn't share it between users.
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(header::VARY, HeaderValue::from_static("Cookie"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::*;
+    use axum::response::IntoResponse;
+    use http::StatusCode;
+    use std::time::Duration;
+    use testdir::testdir;
+
+    #[tokio::test]
+    async fn cache_renders() {
+        let directory = testdir!();
+        let article = directory.join("article.md");
+        let included = directory.join("included.md");
+        std::fs::write(&article, "Hello").unwrap();
+        std::fs::write(&included, "World").unwrap();
+
+        let cache = RenderCache::default();
+        let rendered = RenderedMarkdown {
+            html: "<p>Hello</p>".to_owned(),
+            ..Default::default()
+        };
+        let sources = vec![(article.clone(), FileStamp::of(&article).await)];
+        cache.insert("/article", &User::Anonymous, sources.clone(), &rendered);
+        assert_eq!(cache.get("/article", &User::Anonymous).await.unwrap().0.html, "<p>Hello</p>");
+        assert!(cache.get("/article", &User::SingleUser).await.is_some());
+        assert!(cache.get("/other", &User::Anonymous).await.is_none());
+
+        // Renders including other articles are only reused for the same user, until any of the files change.
+        let rendered = RenderedMarkdown {
+            included: vec![(included.clone(), FileStamp::of(&included).await)],
+            ..rendered
+        };
+        cache.insert("/article", &User::Anonymous, sources, &rendered);
+        assert!(cache.get("/article", &User::Anonymous).await.is_some());
+        assert!(cache.get("/article", &User::SingleUser).await.is_none());
+        std::fs::write(&included, "World!").unwrap();
+        assert!(cache.get("/article", &User::Anonymous).await.is_none());
+
+        cache.insert("/article", &User::Anonymous, Vec::new(), &rendered);
+        cache.clear();
+        assert!(cache.get("/article", &User::Anonymous).await.is_none());
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let mut context = Context::new();
+        context.insert("rendered_cmark", "<p>Hi</p>");
+        context.insert("session_id", "first");
+        let etag = page_etag("article.tera", &context);
+        assert_eq!(etag, page_etag("article.tera", &context.clone()));
+        assert_ne!(etag, page_etag("article_edit.tera", &context));
+
+        // Anything else shown on the page changes the ETag, not just the article.
+        let mut other_session = context.clone();
+        other_session.insert("session_id", "second");
+        assert_ne!(etag, page_etag("article.tera", &other_session));
+
+        let request = |name, value: &str| HeaderMap::from_iter([(name, HeaderValue::from_str(value).unwrap())]);
+        assert!(is_not_modified(&etag, &request(header::IF_NONE_MATCH, &etag)));
+        assert!(is_not_modified(&etag, &request(header::IF_NONE_MATCH, &format!("\"x\", W/{etag}"))));
+        assert!(!is_not_modified(&etag, &request(header::IF_NONE_MATCH, "\"x\"")));
+        assert!(!is_not_modified(&etag, &request(header::IF_MODIFIED_SINCE, "Tue, 14 Nov 2023 22:13:20 GMT")));
+
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let response = with_validators(StatusCode::NOT_MODIFIED.into_response(), &etag, modified);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        assert_eq!(response.headers()[header::LAST_MODIFIED], "Tue, 14 Nov 2023 22:13:20 GMT");
+    }
+}
@@ -1,8 +1,10 @@
-use crate::auth::{Access, Account, AuthenticationMode, Username};
+use crate::auth::{Access, Account, ApiToken, AuthenticationMode, UsedInvite, User, Username};
 use crate::filesystem;
 use crate::filesystem::{FileWriteError, UnhandlableWriteSnafu, WritableFile};
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
 use std::path::{Path, PathBuf};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -26,6 +28,201 @@ pub struct Config {
     pub articles: PathBuf,
     pub assets: PathBuf,
     pub templates: String,
+    /// The file that edits and security events are appended to.
+    #[serde(default = "default_audit_log")]
+    pub audit_log: PathBuf,
+    #[serde(default)]
+    pub login_throttling: LoginThrottlingConfig,
+    #[serde(default)]
+    pub proxy_auth: ProxyAuthConfig,
+    #[serde(default)]
+    pub account_policy: AccountPolicyConfig,
+    #[serde(default)]
+    pub password_hashing: PasswordHashingConfig,
+    #[serde(default)]
+    pub cookies: CookieConfig,
+    #[serde(default)]
+    pub csrf: CsrfConfig,
+    #[serde(default)]
+    pub highlighting: HighlightConfig,
+    #[serde(default)]
+    pub sanitization: SanitizationConfig,
+}
+
+fn default_audit_log() -> PathBuf {
+    PathBuf::from("audit.jsonl")
+}
+
+/// The Argon2id parameters used to hash new passwords. Lower values make logins faster and use less
+/// memory, at the cost of making stolen hashes easier to crack.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PasswordHashingConfig {
+    /// The memory used to hash a password, in KiB.
+    pub memory_kib: u32,
+    /// The number of passes over the memory.
+    pub iterations: u32,
+    /// The number of lanes hashed in parallel.
+    pub parallelism: u32,
+}
+
+impl PasswordHashingConfig {
+    pub fn params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+    }
+}
+
+impl Default for PasswordHashingConfig {
+    fn default() -> Self {
+        PasswordHashingConfig {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Attributes of the session cookie.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CookieConfig {
+    /// Whether browsers only send the cookie over HTTPS. Must be disabled when the site is served over
+    /// plain HTTP, other than on `localhost`, or logging in won't work.
+    pub secure: bool,
+    /// Whether the cookie is hidden from scripts running on the page.
+    pub http_only: bool,
+    /// The path under which browsers send the cookie.
+    pub path: String,
+    /// How long a login lasts when "Remember me" is checked, in days. Other logins last until the
+    /// browser is closed. 0 removes the option.
+    pub remember_me_days: u32,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            secure: true,
+            http_only: true,
+            path: "/".to_owned(),
+            remember_me_days: 30,
+        }
+    }
+}
+
+/// Settings for rejecting cross-site requests.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CsrfConfig {
+    /// Other origins allowed to send requests that change state, such as `https://tools.example.com`.
+    /// Requests from the site itself are always allowed.
+    pub allowed_origins: Vec<String>,
+}
+
+/// Settings for removing unsafe HTML, such as scripts, from rendered articles.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SanitizationConfig {
+    pub enabled: bool,
+    /// Don't sanitize articles whose `edit_access` is restricted to specific accounts.
+    pub trust_account_editors: bool,
+    /// Tags allowed in addition to those markdown produces, like `iframe`.
+    pub allowed_tags: Vec<String>,
+    /// Attributes allowed in addition to the defaults, by tag.
+    pub allowed_attributes: HashMap<String, Vec<String>>,
+}
+
+impl Default for SanitizationConfig {
+    fn default() -> Self {
+        SanitizationConfig {
+            enabled: true,
+            trust_account_editors: false,
+            allowed_tags: Vec::new(),
+            allowed_attributes: HashMap::new(),
+        }
+    }
+}
+
+/// Settings for highlighting the syntax of fenced code blocks.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HighlightConfig {
+    /// The name of the built-in theme used to color code.
+    pub theme: String,
+    /// The theme used instead when the browser prefers a dark color scheme. Empty to always use `theme`.
+    pub dark_theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig {
+            theme: "InspiredGitHub".to_owned(),
+            dark_theme: "base16-ocean.dark".to_owned(),
+        }
+    }
+}
+
+/// Settings for limiting repeated failed login attempts.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoginThrottlingConfig {
+    /// Whether failed login attempts are throttled at all.
+    pub enabled: bool,
+    /// The number of consecutive failed attempts allowed before a lockout occurs.
+    pub max_attempts: u32,
+    /// The number of consecutive failed attempts against one user, from any address, allowed before
+    /// the user is locked out. Zero to only count failures per address.
+    pub max_user_attempts: u32,
+    /// The duration of the first lockout, in seconds. Each further failure doubles the duration.
+    pub lockout_seconds: u64,
+    /// The maximum duration of a lockout, in seconds.
+    pub max_lockout_seconds: u64,
+    /// The number of seconds after the last failed attempt until failures are forgotten.
+    pub reset_seconds: u64,
+}
+
+impl Default for LoginThrottlingConfig {
+    fn default() -> Self {
+        LoginThrottlingConfig {
+            enabled: true,
+            max_attempts: 5,
+            max_user_attempts: 20,
+            lockout_seconds: 30,
+            max_lockout_seconds: 3600,
+            reset_seconds: 86400,
+        }
+    }
+}
+
+/// Requirements for new usernames and passwords. Existing accounts aren't affected.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccountPolicyConfig {
+    /// The minimum number of characters in a password.
+    pub min_password_length: usize,
+    /// The minimum estimated strength of a password, from 0 (trivially guessable) to 4 (very hard to guess).
+    pub min_password_strength: u8,
+    /// Whether to reject a built-in list of commonly used passwords.
+    pub deny_common_passwords: bool,
+    /// The characters allowed in usernames, in addition to ASCII letters and digits.
+    pub username_characters: String,
+    /// The maximum number of characters in a username.
+    pub max_username_length: usize,
+    /// Whether usernames differing only in case are the same when logging in and checking for existing
+    /// accounts. Access lists always compare usernames exactly.
+    pub case_insensitive_usernames: bool,
+}
+
+impl Default for AccountPolicyConfig {
+    fn default() -> Self {
+        AccountPolicyConfig {
+            min_password_length: 8,
+            min_password_strength: 2,
+            deny_common_passwords: true,
+            username_characters: "-_.".to_owned(),
+            max_username_length: 32,
+            case_insensitive_usernames: true,
+        }
+    }
 }
 
 impl Config {
@@ -33,7 +230,7 @@ impl Config {
         use base64::prelude::*;
         use rand_core::RngCore;
         let mut random_key = vec![0u8; 64];
-        rand_core::OsRng::default().fill_bytes(&mut random_key);
+        rand_core::OsRng.fill_bytes(&mut random_key);
         let key_string = BASE64_STANDARD.encode(&random_key);
 
         self.secret_key = random_key;
@@ -47,14 +244,43 @@ impl Config {
         let mut file = filesystem::ReadableFile::open(path.as_ref()).await.context(IoSnafu)?;
         let mut str = String::new();
         file.reader.read_to_string(&mut str).await.context(IoSnafu)?;
-        Ok(toml::from_str(&str).context(SerdeSnafu)?)
+        toml::from_str(&str).context(SerdeSnafu)
+    }
+}
+
+/// Settings for authenticating through a reverse proxy, used in the Proxy authentication mode.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProxyAuthConfig {
+    /// The name of the request header containing the username of the authenticated user.
+    pub header: String,
+    /// The addresses of the reverse proxies trusted to set the header. The header is ignored on
+    /// requests from any other address.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ProxyAuthConfig {
+    fn default() -> Self {
+        ProxyAuthConfig {
+            header: "Remote-User".to_owned(),
+            trusted_proxies: vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from(Ipv6Addr::LOCALHOST)],
+        }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AccountConfig {
     pub single_password: Option<String>,
+    /// The base32 encoded TOTP secret of the single user, if enrolled in two-factor authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub single_totp_secret: Option<String>,
+    /// The API tokens created by the single user.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub single_tokens: Vec<ApiToken>,
     pub accounts: Vec<Account>,
+    /// The invites that have been accepted and haven't expired yet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub used_invites: Vec<UsedInvite>,
 }
 
 impl AccountConfig {
@@ -67,19 +293,110 @@ impl AccountConfig {
         }
     }
 
-    pub fn generate_single_user_password(&mut self) -> String {
+    pub fn generate_single_user_password(&mut self, hashing: &PasswordHashingConfig) -> String {
         use base64::prelude::*;
         use rand_core::RngCore;
 
         // Generate a new 120-bit password and encode with base64
         let mut bits = vec![0u8; 15];
-        rand_core::OsRng::default().fill_bytes(&mut bits);
+        rand_core::OsRng.fill_bytes(&mut bits);
         let password = BASE64_STANDARD.encode(&bits);
 
-        self.single_password = Some(crate::auth::hash_password(&password));
+        self.single_password = Some(crate::auth::hash_password(&password, hashing));
         password
     }
 
+    /// Gets the TOTP secret of the specified user, if they have enrolled in two-factor authentication.
+    pub fn totp_secret(&self, user: &User) -> Option<&str> {
+        match user {
+            User::Anonymous => None,
+            User::SingleUser => self.single_totp_secret.as_deref(),
+            User::Account(username) => self.find_by_username(username).and_then(|acc| acc.totp_secret()),
+        }
+    }
+
+    /// Gets the API tokens of the specified user. [None] if the user can't have tokens.
+    pub fn tokens(&self, user: &User) -> Option<&Vec<ApiToken>> {
+        match user {
+            User::Anonymous => None,
+            User::SingleUser => Some(&self.single_tokens),
+            User::Account(username) => self.find_by_username(username).map(|acc| &acc.tokens),
+        }
+    }
+
+    /// Gets the API tokens of the specified user for modification. [None] if the user can't have tokens.
+    pub fn tokens_mut(&mut self, user: &User) -> Option<&mut Vec<ApiToken>> {
+        match user {
+            User::Anonymous => None,
+            User::SingleUser => Some(&mut self.single_tokens),
+            User::Account(username) => self.find_by_username_mut(username).map(|acc| &mut acc.tokens),
+        }
+    }
+
+    /// Finds the user that owns the specified API token. Only users that can log in under the
+    /// specified authentication mode are considered.
+    pub fn find_token_owner(&self, secret: &str, auth_mode: AuthenticationMode) -> Option<User> {
+        match auth_mode {
+            AuthenticationMode::Anonymous => None,
+            AuthenticationMode::Single => self
+                .single_tokens
+                .iter()
+                .any(|token| token.verify(secret))
+                .then_some(User::SingleUser),
+            AuthenticationMode::Multi | AuthenticationMode::Proxy => self
+                .accounts
+                .iter()
+                .filter(|acc| !acc.disabled)
+                .find(|acc| acc.tokens.iter().any(|token| token.verify(secret)))
+                .map(|acc| User::Account(acc.username.clone())),
+        }
+    }
+
+    /// Checks if the invite with the specified nonce has already been accepted.
+    pub fn is_invite_used(&self, nonce: &str) -> bool {
+        self.used_invites.iter().any(|invite| invite.nonce == nonce)
+    }
+
+    /// Records that the invite has been accepted, forgetting invites that have since expired.
+    pub fn record_used_invite(&mut self, nonce: String, expires: u64) {
+        let now = crate::unix_time();
+        self.used_invites.retain(|invite| invite.expires > now);
+        self.used_invites.push(UsedInvite { nonce, expires });
+    }
+
+    /// Hashes the password of the specified user again if its current hash uses outdated parameters.
+    /// The password must already have been verified. The new hash is saved with [Self::replace_outdated_hash].
+    pub fn rehashed_password(&self, user: &User, password: &str, hashing: &PasswordHashingConfig) -> Option<String> {
+        let outdated = match user {
+            User::Anonymous => false,
+            User::SingleUser => {
+                let hash = self.single_password.as_deref();
+                hash.is_some_and(|hash| crate::auth::password_needs_rehash(hash, hashing))
+            }
+            User::Account(username) => self.find_by_username(username).is_some_and(|acc| acc.has_outdated_hash(hashing)),
+        };
+        outdated.then(|| crate::auth::hash_password(password, hashing))
+    }
+
+    /// Replaces the outdated password hash of the specified user with a new hash of the same password.
+    /// Nothing is replaced if the hash was already updated, or the password changed. Returns true if the
+    /// hash was replaced.
+    pub fn replace_outdated_hash(&mut self, user: &User, hash: String, hashing: &PasswordHashingConfig) -> bool {
+        match user {
+            User::Anonymous => false,
+            User::SingleUser => match &self.single_password {
+                Some(existing) if crate::auth::password_needs_rehash(existing, hashing) => {
+                    self.single_password = Some(hash);
+                    true
+                }
+                _ => false,
+            },
+            User::Account(username) => self
+                .find_by_username_mut(username)
+                .is_some_and(|acc| acc.replace_outdated_hash(hash, hashing)),
+        }
+    }
+
     pub fn find_by_username_mut(&mut self, username: &Username) -> Option<&mut Account> {
         self.accounts.iter_mut().find(|acc| &acc.username == username)
     }
@@ -95,7 +412,7 @@ impl AccountConfig {
         let mut file = filesystem::ReadableFile::open(path.as_ref()).await.context(IoSnafu)?;
         let mut str = String::new();
         file.reader.read_to_string(&mut str).await.context(IoSnafu)?;
-        Ok(toml::from_str(&str).context(SerdeSnafu)?)
+        toml::from_str(&str).context(SerdeSnafu)
     }
 
     pub async fn write_to_file<P>(&self, path: P) -> Result<(), FileWriteError>
@@ -105,7 +422,7 @@ impl AccountConfig {
         let filepath = path.as_ref();
         let mut file = WritableFile::open(filepath).await?;
         let toml = toml::to_string_pretty(&self).expect("TOML Serialization should always succeed.");
-        (&mut file.writer)
+        file.writer
             .write_all(toml.as_bytes())
             .await
             .with_context(|_| UnhandlableWriteSnafu { filepath })?;
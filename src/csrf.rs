@@ -0,0 +1,92 @@
+use crate::auth::Session;
+use crate::config::CsrfConfig;
+use crate::{AppState, ErrorResponse, render_error};
+use axum::extract::State;
+use axum::middleware::Next;
+use axum_core::body::Body;
+use axum_core::response::Response;
+use http::{HeaderMap, Method, Request, header};
+
+/// Rejects cross-site requests that could change state, based on the `Sec-Fetch-Site` and `Origin`
+/// headers browsers attach to them. This protects every route regardless of how it handles forms,
+/// in addition to the session IDs checked by [crate::extractors::Form].
+pub async fn csrf_middleware(State(state): State<AppState>, request: Request<Body>, next: Next) -> Response {
+    if is_safe_method(request.method()) || is_same_origin(&state.config.csrf, request.headers()) {
+        return next.run(request).await;
+    }
+
+    render_error(&state, &Session::default(), ErrorResponse::cross_site_request())
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+}
+
+/// Checks if the request was made by this site, or one of the allowed origins. Requests without
+/// either header weren't made by a modern browser, so can't have been forged by another site.
+fn is_same_origin(config: &CsrfConfig, headers: &HeaderMap) -> bool {
+    let origin = headers.get(header::ORIGIN).and_then(|origin| origin.to_str().ok());
+    let is_allowed = origin.is_some_and(|origin| config.allowed_origins.iter().any(|allowed| same_origin(allowed, origin)));
+
+    let fetch_site = headers.get("Sec-Fetch-Site").and_then(|site| site.to_str().ok());
+    match (fetch_site, origin) {
+        // "none" means the user initiated the request themselves, such as by opening a bookmark.
+        (Some("same-origin" | "none"), _) => true,
+        // Other sites on the same domain are still a different origin, so are treated like any other site.
+        (Some(_), _) => is_allowed,
+        (None, Some(origin)) => is_allowed || origin_matches_host(origin, headers),
+        (None, None) => true,
+    }
+}
+
+/// Checks if the origin refers to the host the request was sent to.
+fn origin_matches_host(origin: &str, headers: &HeaderMap) -> bool {
+    let Some((_, authority)) = origin.split_once("://") else {
+        // Includes the "null" origin of sandboxed and privacy-sensitive contexts.
+        return false;
+    };
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .is_some_and(|host| host.eq_ignore_ascii_case(authority))
+}
+
+fn same_origin(allowed: &str, origin: &str) -> bool {
+    allowed.trim_end_matches('/').eq_ignore_ascii_case(origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CsrfConfig;
+    use crate::csrf::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, "wiki.example.com".parse().unwrap());
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn cross_site_requests() {
+        let config = CsrfConfig {
+            allowed_origins: vec!["https://tools.example.com/".to_owned()],
+        };
+        let check = |pairs: &[(&'static str, &'static str)]| is_same_origin(&config, &headers(pairs));
+
+        assert!(check(&[]));
+        assert!(check(&[("Sec-Fetch-Site", "same-origin")]));
+        assert!(check(&[("Sec-Fetch-Site", "none")]));
+        assert!(!check(&[("Sec-Fetch-Site", "cross-site"), ("Origin", "https://evil.example.net")]));
+        assert!(!check(&[("Sec-Fetch-Site", "same-site"), ("Origin", "https://blog.example.com")]));
+        assert!(check(&[("Sec-Fetch-Site", "same-site"), ("Origin", "https://tools.example.com")]));
+
+        // Browsers that don't send Fetch Metadata are checked by their origin alone.
+        assert!(check(&[("Origin", "https://wiki.example.com")]));
+        assert!(check(&[("Origin", "https://tools.example.com")]));
+        assert!(!check(&[("Origin", "https://evil.example.net")]));
+        assert!(!check(&[("Origin", "null")]));
+    }
+}
@@ -1,12 +1,16 @@
+use crate::audit::Auditor;
 use crate::auth::{Session, User};
+use crate::authentication::TokenAuthenticated;
 use crate::{AntiCsrfForm, AppState, ErrorResponse};
+use axum::extract::ConnectInfo;
 use axum_core::extract::{FromRef, FromRequest, FromRequestParts};
-use axum_extra::extract::SignedCookieJar;
+use axum_extra::extract::PrivateCookieJar;
 use axum_extra::extract::cookie::Key;
 use http::Request;
 use http::request::Parts;
 use serde::de::DeserializeOwned;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
 
 impl<S> FromRequestParts<S> for Session
@@ -17,8 +21,13 @@ where
     type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Sessions established by other means of authentication take precedence over the cookie.
+        if let Some(session) = parts.extensions.get::<Session>() {
+            return Ok(session.clone());
+        }
+
         let state = AppState::from_ref(state);
-        let jar: SignedCookieJar<Key> = SignedCookieJar::from_request_parts(parts, &state).await.unwrap();
+        let jar: PrivateCookieJar<Key> = PrivateCookieJar::from_request_parts(parts, &state).await.unwrap();
 
         Ok(match jar.get("session") {
             None => Session::default(),
@@ -41,6 +50,45 @@ where
     }
 }
 
+/// The address of the connected client. [None] if the connection info isn't available, such as when
+/// the router isn't served with connection info.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddress(pub Option<IpAddr>);
+
+impl<S> FromRequestParts<S> for ClientAddress
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+        Ok(ClientAddress(address))
+    }
+}
+
+impl<S> FromRequestParts<S> for Auditor
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::RequestPartsExt;
+        let user = parts.extract_with_state::<User, S>(state).await?;
+        let ClientAddress(address) = parts.extract::<ClientAddress>().await?;
+        Ok(Auditor {
+            log: AppState::from_ref(state).audit,
+            user,
+            address,
+        })
+    }
+}
+
 impl FromRef<AppState> for Key {
     fn from_ref(input: &AppState) -> Self {
         Key::from(&input.config.secret_key)
@@ -63,11 +111,12 @@ where
         use axum::RequestPartsExt;
         let (mut parts, body) = req.into_parts();
         let session = parts.extract_with_state::<Session, S>(state).await.unwrap();
+        let token_authenticated = parts.extensions.get::<TokenAuthenticated>().is_some();
         let form = axum::extract::Form::from_request(Request::from_parts(parts, body), state)
             .await
             .map(|f: axum::Form<T>| Form(f.0))
-            .map_err(|rej| ErrorResponse::from(rej))?;
-        if !form.0.is_valid(session.id.as_deref()) {
+            .map_err(ErrorResponse::from)?;
+        if !token_authenticated && !form.0.is_valid(session.id.as_deref()) {
             return Err(ErrorResponse::bad_request());
         }
         Ok(form)
@@ -26,7 +26,7 @@ impl WritableFile {
         let tmp_path = path.with_added_extension("tmp");
         let file = File::create_new(&tmp_path)
             .await
-            .or_else(|e| Err(FileWriteError::from_io_error_tmp(&path, e, &tmp_path)))?;
+            .map_err(|e| FileWriteError::from_io_error_tmp(&path, e, &tmp_path))?;
         let writer = BufWriter::new(file);
         Ok(WritableFile { path, writer, tmp_path })
     }
@@ -0,0 +1,84 @@
+use crate::config::HighlightConfig;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::sync::LazyLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassStyle, ClassedHTMLGenerator, css_for_theme_with_class_style};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+/// Highlighted code is marked up with classes rather than inline colors, so that the theme can be
+/// changed, or follow the browser's color scheme, without rendering articles again.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+/// The syntaxes are only loaded once code is first highlighted, since it takes a moment.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+
+#[derive(Debug, Snafu)]
+pub enum ThemeError {
+    #[snafu(display("Unknown theme `{name}`. Available themes are: {available}"))]
+    UnknownTheme { name: String, available: String },
+    #[snafu(display("Failed to generate the stylesheet for theme `{name}`: {source}"))]
+    Stylesheet { name: String, source: syntect::Error },
+}
+
+/// Generates the stylesheet coloring highlighted code with the configured themes.
+pub fn theme_css(config: &HighlightConfig) -> Result<String, ThemeError> {
+    let themes = ThemeSet::load_defaults();
+    let css_for = |name: &str| {
+        let theme = themes.themes.get(name).with_context(|| UnknownThemeSnafu {
+            name,
+            available: themes.themes.keys().cloned().collect::<Vec<_>>().join(", "),
+        })?;
+        css_for_theme_with_class_style(theme, CLASS_STYLE).context(StylesheetSnafu { name })
+    };
+
+    let mut css = css_for(&config.theme)?;
+    if !config.dark_theme.is_empty() {
+        css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        css.push_str(&css_for(&config.dark_theme)?);
+        css.push_str("}\n");
+    }
+    Ok(css)
+}
+
+/// Highlights a fenced code block as HTML. [None] if the language isn't recognized, in which case
+/// the block should be rendered as plain text.
+pub fn highlight_block(info: &str, code: &str) -> Option<String> {
+    // The language is the first word of the info string, such as `rust` in "rust,ignore".
+    let language = info.split([',', ' ', '\t']).next().filter(|language| !language.is_empty())?;
+    let syntax = SYNTAXES.find_syntax_by_token(language)?;
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        generator.parse_html_for_line_which_includes_newline(line).ok()?;
+    }
+    let language = tera::escape_html(language);
+    Some(format!(
+        "<pre class=hl-code><code class=\"language-{language}\">{}</code></pre>\n",
+        generator.finalize()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::HighlightConfig;
+    use crate::highlight::*;
+
+    #[test]
+    fn highlight_code() {
+        let html = highlight_block("rust,ignore", "fn main() {}\n").unwrap();
+        assert!(html.starts_with("<pre class=hl-code><code class=\"language-rust\"><span class=\"hl-source hl-rust\">"));
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+        assert_eq!(highlight_block("not-a-language", "text"), None);
+        assert_eq!(highlight_block("", "text"), None);
+
+        let css = theme_css(&HighlightConfig::default()).unwrap();
+        assert!(css.contains(".hl-code"));
+        assert!(css.contains("@media (prefers-color-scheme: dark)"));
+        let unknown = HighlightConfig {
+            theme: "Nonexistent".to_owned(),
+            dark_theme: String::new(),
+        };
+        assert!(matches!(theme_css(&unknown), Err(ThemeError::UnknownTheme { .. })));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::auth::{Account, Username};
+use crate::config::{AccountConfig, AccountPolicyConfig};
+use crate::policy;
+use snafu::Snafu;
+use subtle::ConstantTimeEq;
+
+/// The alphabet used by crypt(3) style hashes, in place of standard base64.
+const CRYPT_ALPHABET: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const APR1_PREFIX: &str = "$apr1$";
+
+/// A line of an htpasswd file that couldn't be imported.
+#[derive(Debug, Snafu)]
+pub enum SkippedLine {
+    #[snafu(display("Line {line} isn't a `username:hash` pair."))]
+    Malformed { line: usize },
+    #[snafu(display("Line {line}: `{username}` uses an unsupported hash. Only bcrypt and `$apr1$` hashes can be imported."))]
+    UnsupportedHash { line: usize, username: String },
+    #[snafu(display("Line {line}: {source}"))]
+    InvalidUsername {
+        line: usize,
+        source: policy::PolicyViolation,
+    },
+}
+
+/// The outcome of importing an htpasswd file.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<Username>,
+    pub skipped: Vec<SkippedLine>,
+}
+
+/// Checks if the hash is in one of the htpasswd formats that can be verified.
+pub fn is_htpasswd_hash(hash: &str) -> bool {
+    hash.starts_with(APR1_PREFIX) || hash.starts_with("$2y$") || hash.starts_with("$2a$") || hash.starts_with("$2b$")
+}
+
+/// Verifies a password against an htpasswd hash. [None] if the hash isn't in a supported htpasswd format.
+pub fn verify(password: &str, hash: &str) -> Option<bool> {
+    if let Some(rest) = hash.strip_prefix(APR1_PREFIX) {
+        let (salt, _) = rest.split_once('$')?;
+        // Compared in constant time, so the time taken doesn't reveal how much of the hash matched.
+        return Some(apr1(password, salt).as_bytes().ct_eq(hash.as_bytes()).into());
+    }
+    if is_htpasswd_hash(hash) {
+        return Some(bcrypt::verify(password, hash).unwrap_or(false));
+    }
+    None
+}
+
+/// Adds an account for each user in the htpasswd file, keeping their existing password hash. Users
+/// whose username is taken or not allowed by the account policy are skipped, as are users with
+/// hashes that can't be verified.
+pub fn import(contents: &str, policy: &AccountPolicyConfig, account_config: &mut AccountConfig) -> ImportReport {
+    let mut report = ImportReport::default();
+    for (index, text) in contents.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let Some((username, hash)) = text.split_once(':').filter(|(username, hash)| !username.is_empty() && !hash.is_empty())
+        else {
+            report.skipped.push(SkippedLine::Malformed { line });
+            continue;
+        };
+        if !is_htpasswd_hash(hash) {
+            let username = username.to_owned();
+            report.skipped.push(SkippedLine::UnsupportedHash { line, username });
+            continue;
+        }
+
+        let username = Username::from(username);
+        if let Err(source) = policy::validate_username(policy, account_config, &username, None) {
+            report.skipped.push(SkippedLine::InvalidUsername { line, source });
+            continue;
+        }
+        account_config.accounts.push(Account::with_hash(username.clone(), hash));
+        report.imported.push(username);
+    }
+    report
+}
+
+/// Hashes the password with Apache's variant of the MD5 crypt algorithm.
+fn apr1(password: &str, salt: &str) -> String {
+    use md5::{Digest, Md5};
+
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let alternate = Md5::new().chain_update(password).chain_update(salt).chain_update(password).finalize();
+    let mut context = Md5::new().chain_update(password).chain_update(APR1_PREFIX).chain_update(salt);
+    for chunk in (0..password.len()).step_by(16) {
+        context.update(&alternate[..(password.len() - chunk).min(16)]);
+    }
+    let mut length = password.len();
+    while length > 0 {
+        match length & 1 {
+            1 => context.update([0]),
+            _ => context.update(&password[..1]),
+        }
+        length >>= 1;
+    }
+    let mut digest = context.finalize();
+
+    // Deliberately slows the hash down, which was significant at the time it was designed.
+    for round in 0..1000 {
+        let mut context = Md5::new();
+        match round & 1 {
+            1 => context.update(password),
+            _ => context.update(digest),
+        }
+        if round % 3 != 0 {
+            context.update(salt);
+        }
+        if round % 7 != 0 {
+            context.update(password);
+        }
+        match round & 1 {
+            1 => context.update(digest),
+            _ => context.update(password),
+        }
+        digest = context.finalize();
+    }
+
+    let mut encoded = String::with_capacity(22);
+    let mut encode = |value: u32, chars: usize| {
+        for i in 0..chars {
+            encoded.push(CRYPT_ALPHABET[((value >> (6 * i)) & 0x3f) as usize] as char);
+        }
+    };
+    for [a, b, c] in [[0, 6, 12], [1, 7, 13], [2, 8, 14], [3, 9, 15], [4, 10, 5]] {
+        encode(u32::from(digest[a]) << 16 | u32::from(digest[b]) << 8 | u32::from(digest[c]), 4);
+    }
+    encode(u32::from(digest[11]), 2);
+
+    format!("{APR1_PREFIX}{}${encoded}", String::from_utf8_lossy(salt))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::htpasswd::*;
+
+    #[test]
+    fn verify_hashes() {
+        assert_eq!(verify("myPassword", "$apr1$qHDFfhPC$nITSVHgYbDAK1Y0acGRnY0"), Some(true));
+        assert_eq!(verify("correct horse", "$apr1$abc$wQiFhKV487RKJ400idf4c/"), Some(true));
+        assert_eq!(verify("wrong", "$apr1$abc$wQiFhKV487RKJ400idf4c/"), Some(false));
+
+        let bcrypt = bcrypt::hash_with_result("hunter22", 4).unwrap().format_for_version(bcrypt::Version::TwoY);
+        assert_eq!(verify("hunter22", &bcrypt), Some(true));
+        assert_eq!(verify("hunter2", &bcrypt), Some(false));
+
+        assert_eq!(verify("myPassword", "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA"), None);
+    }
+
+    #[test]
+    fn import_accounts() {
+        let mut account_config = AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: vec![Account::with_hash("alex".into(), "$apr1$abc$wQiFhKV487RKJ400idf4c/")],
+            used_invites: Vec::new(),
+        };
+        let contents = "# Exported from the old wiki\n\
+            sam:$apr1$qHDFfhPC$nITSVHgYbDAK1Y0acGRnY0\n\
+            ALEX:$apr1$abc$wQiFhKV487RKJ400idf4c/\n\
+            morgan:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n\
+            not a user\n";
+        let report = import(contents, &AccountPolicyConfig::default(), &mut account_config);
+
+        assert_eq!(report.imported, vec![Username::from("sam")]);
+        assert_eq!(report.skipped.len(), 3);
+        assert!(matches!(report.skipped[0], SkippedLine::InvalidUsername { line: 3, .. }));
+        assert!(matches!(report.skipped[1], SkippedLine::UnsupportedHash { line: 4, .. }));
+        assert!(matches!(report.skipped[2], SkippedLine::Malformed { line: 5 }));
+        assert!(account_config.find_by_username(&"sam".into()).unwrap().verify_password("myPassword").is_ok());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::article::RawArticle;
+use crate::auth::Authorization;
+use crate::cache::{FileStamp, Source};
+use crate::markdown::{self, ArticleRenderer, TocEntry};
+use crate::routes::articles::get_paths;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, TextMergeStream};
+use std::collections::{HashMap, HashSet};
+use tera::escape_html;
+
+/// How many levels deep articles may be included in each other.
+pub const MAX_DEPTH: usize = 4;
+
+/// How many articles may be included in total while rendering an article, including those included
+/// by the included articles.
+pub const MAX_INCLUDES: usize = 32;
+
+/// An article included in another with `{{< include "/path" >}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Include {
+    /// The rendered HTML of the included article.
+    Article(String),
+    /// The article doesn't exist, or the viewer isn't allowed to view it. These aren't told apart,
+    /// so that the existence of private articles isn't revealed.
+    Unavailable,
+    /// The article includes the article including it, directly or indirectly.
+    Cycle,
+    /// The article is nested deeper than [MAX_DEPTH].
+    TooDeep,
+    /// [MAX_INCLUDES] articles have already been included.
+    TooMany,
+}
+
+impl Include {
+    /// The HTML shown in place of the include directive.
+    pub fn html(&self, target: &str) -> String {
+        let target = escape_html(target);
+        let error = match self {
+            Include::Article(html) => return html.clone(),
+            Include::Unavailable => format!("Couldn't include <code>{target}</code>, since it doesn't exist or is private."),
+            Include::Cycle => format!("Couldn't include <code>{target}</code>, since it includes this article."),
+            Include::TooDeep => {
+                format!("Couldn't include <code>{target}</code>, since articles can only be nested {MAX_DEPTH} deep.")
+            }
+            Include::TooMany => {
+                format!("Couldn't include <code>{target}</code>, since a page can only include {MAX_INCLUDES} articles.")
+            }
+        };
+        format!("<p class=include-error>{error}</p>\n")
+    }
+}
+
+/// Tracks the articles included while rendering an article, shared with the articles it includes.
+#[derive(Debug)]
+pub struct Nesting {
+    /// The URLs of the articles being rendered, outermost first.
+    stack: Vec<String>,
+    /// How many articles have been included so far, at any depth.
+    included: usize,
+    /// The heading IDs used by the articles rendered so far, so that each heading on the page has its own.
+    heading_ids: HashSet<String>,
+}
+
+impl Nesting {
+    /// Starts rendering the article at the specified URL.
+    pub fn new(url: &str) -> Nesting {
+        Nesting {
+            stack: vec![url.to_owned()],
+            included: 0,
+            heading_ids: HashSet::new(),
+        }
+    }
+
+    pub fn heading_ids(&self) -> &HashSet<String> {
+        &self.heading_ids
+    }
+
+    /// Reserves the IDs of the headings in the table of contents of a rendered article.
+    pub fn reserve_heading_ids(&mut self, toc: &[TocEntry]) {
+        for entry in toc {
+            self.heading_ids.insert(entry.id.clone());
+            self.reserve_heading_ids(&entry.children);
+        }
+    }
+}
+
+/// The articles included in an article, rendered ahead of time since reading them is asynchronous.
+#[derive(Debug, Default)]
+pub struct Includes {
+    includes: HashMap<String, Include>,
+    /// The files of the included articles, and those included in them.
+    sources: Vec<Source>,
+}
+
+impl Includes {
+    /// Renders the articles included in the markdown of the article at `url`, which must be the
+    /// innermost article of `nesting`.
+    pub async fn resolve(renderer: &ArticleRenderer<'_>, url: &str, markdown: &str, nesting: &mut Nesting) -> Includes {
+        let mut includes = Includes::default();
+        for target in include_targets(markdown) {
+            if includes.includes.contains_key(&target) {
+                continue;
+            }
+            let include = Box::pin(Self::include(renderer, url, &target, nesting, &mut includes.sources)).await;
+            includes.includes.insert(target, include);
+        }
+        includes
+    }
+
+    async fn include(
+        renderer: &ArticleRenderer<'_>,
+        url: &str,
+        target: &str,
+        nesting: &mut Nesting,
+        sources: &mut Vec<Source>,
+    ) -> Include {
+        let path = match target.strip_prefix('/') {
+            Some(absolute) => absolute.to_owned(),
+            None => format!("{}/{target}", markdown::directory(url)),
+        };
+        let Some(paths) = get_paths(renderer.articles, &path) else {
+            return Include::Unavailable;
+        };
+        if nesting.stack.contains(&paths.url) {
+            return Include::Cycle;
+        }
+        if nesting.stack.len() > MAX_DEPTH {
+            return Include::TooDeep;
+        }
+        if nesting.included == MAX_INCLUDES {
+            return Include::TooMany;
+        }
+        nesting.included += 1;
+        sources.push((paths.md.clone(), FileStamp::of(&paths.md).await));
+        let Ok(article) = RawArticle::read_from_path(&paths.md, &paths.url).await else {
+            return Include::Unavailable;
+        };
+        if renderer.user.check_authorization(&article.metadata.view_access) != Authorization::Authorized {
+            return Include::Unavailable;
+        }
+
+        nesting.stack.push(paths.url.clone());
+        let rendered = renderer.render_nested(&paths.url, &article, nesting).await;
+        nesting.stack.pop();
+        sources.extend(rendered.included);
+        Include::Article(rendered.html)
+    }
+
+    /// Gets the included article for the target of an include directive, as written.
+    pub fn get(&self, target: &str) -> Option<&Include> {
+        self.includes.get(target)
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+}
+
+/// Parses an include directive like `{{< include "/recipes/basics/roux" >}}`, returning the path of
+/// the article to include.
+pub fn parse_directive(text: &str) -> Option<&str> {
+    let directive = text.trim().strip_prefix("{{<")?.strip_suffix(">}}")?.trim();
+    let target = directive.strip_prefix("include")?.trim_start();
+    // Smart punctuation curls the quotes, which are accepted either way.
+    let target = target.strip_prefix(['"', '“'])?.strip_suffix(['"', '”'])?.trim();
+    (!target.is_empty()).then_some(target)
+}
+
+/// Finds the targets of the include directives in the markdown, which must be paragraphs of their own.
+fn include_targets(markdown: &str) -> Vec<String> {
+    let events = TextMergeStream::new(Parser::new_ext(markdown, Options::all())).collect::<Vec<_>>();
+    events
+        .windows(3)
+        .filter_map(|window| match window {
+            [Event::Start(Tag::Paragraph), Event::Text(text), Event::End(TagEnd::Paragraph)] => {
+                parse_directive(text).map(|target| target.to_owned())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{Access, User};
+    use crate::config::{AccountConfig, AccountPolicyConfig, SanitizationConfig};
+    use crate::include::*;
+    use crate::metadata::Metadata;
+    use crate::sanitize::Sanitizer;
+    use crate::titles::TitleIndex;
+    use std::path::Path;
+    use testdir::testdir;
+
+    async fn write(articles: &Path, url: &str, view_access: Access, markdown: &str) {
+        let article = RawArticle {
+            metadata: Metadata {
+                title: url.to_owned(),
+                edit_access: Access::Authenticated,
+                view_access,
+            },
+            markdown: markdown.to_owned(),
+        };
+        let filepath = articles.join(&url[1..]).with_extension("md");
+        std::fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+        article.write_to_path(&filepath, url).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn include_articles() {
+        let articles = testdir!();
+        write(&articles, "/safety", Access::Anonymous, "Unplug it first.").await;
+        write(&articles, "/private", Access::Authenticated, "Secret.").await;
+        write(&articles, "/loop/a", Access::Anonymous, "{{< include \"b\" >}}").await;
+        write(&articles, "/loop/b", Access::Anonymous, "{{< include \"/loop/a\" >}}").await;
+        for depth in 1..=6 {
+            write(&articles, &format!("/deep/{depth}"), Access::Anonymous, &format!("{{{{< include \"{}\" >}}}}", depth + 1))
+                .await;
+        }
+
+        let accounts = AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: Vec::new(),
+            used_invites: Vec::new(),
+        };
+        let policy = AccountPolicyConfig::default();
+        let sanitizer = Sanitizer::new(&SanitizationConfig::default()).unwrap();
+        let titles = TitleIndex::default();
+        let render = async |url: &str, user: &User| {
+            let renderer = ArticleRenderer {
+                articles: &articles,
+                titles: &titles,
+                policy: &policy,
+                accounts: &accounts,
+                sanitizer: &sanitizer,
+                user,
+            };
+            let path = articles.join(&url[1..]).with_extension("md");
+            let article = RawArticle::read_from_path(&path, url).await.unwrap();
+            renderer.render(url, &article).await.html
+        };
+
+        write(&articles, "/dishwasher", Access::Anonymous, "{{< include \"/safety\" >}}\n\n{{< include \"/private\" >}}").await;
+        assert_eq!(
+            render("/dishwasher", &User::Anonymous).await,
+            "<p>Unplug it first.</p>\n<p class=\"include-error\">Couldn't include <code>/private</code>, since it doesn't \
+             exist or is private.</p>\n"
+        );
+        assert_eq!(render("/dishwasher", &User::SingleUser).await, "<p>Unplug it first.</p>\n<p>Secret.</p>\n");
+
+        assert_eq!(
+            render("/loop/a", &User::Anonymous).await,
+            "<p class=\"include-error\">Couldn't include <code>/loop/a</code>, since it includes this article.</p>\n"
+        );
+        let deep = render("/deep/1", &User::Anonymous).await;
+        assert!(deep.contains("Couldn't include <code>6</code>, since articles can only be nested 4 deep."));
+
+        // Each included article counts toward the total, however it was included.
+        let mut hub = String::new();
+        for leaf in 1..=MAX_INCLUDES + 1 {
+            write(&articles, &format!("/wide/{leaf}"), Access::Anonymous, "Leaf.").await;
+            hub.push_str(&format!("{{{{< include \"{leaf}\" >}}}}\n\n"));
+        }
+        write(&articles, "/wide/hub", Access::Anonymous, &hub).await;
+        let wide = render("/wide/hub", &User::Anonymous).await;
+        assert_eq!(wide.matches("<p>Leaf.</p>").count(), MAX_INCLUDES);
+        assert!(wide.contains("Couldn't include <code>33</code>, since a page can only include 32 articles."));
+
+        // Headings keep their IDs unique across the page, including those of included articles.
+        write(&articles, "/manual/filter", Access::Anonymous, "## Notes\n\n{{< include \"parts\" >}}").await;
+        write(&articles, "/manual/parts", Access::Anonymous, "## Notes {#spares}\n").await;
+        write(&articles, "/manual/index", Access::Anonymous, "# Notes {#spares}\n\n{{< include \"filter\" >}}\n\n## Notes").await;
+        let manual = render("/manual/index", &User::Anonymous).await;
+        let ids = manual.split(" id=\"").skip(1).map(|rest| rest.split('"').next().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids, ["spares-1", "notes", "spares", "notes-1"]);
+    }
+}
@@ -1,20 +1,46 @@
 mod article;
+mod audit;
 mod auth;
+mod authentication;
+mod backlinks;
+mod cache;
 mod config;
+mod csrf;
 mod extractors;
 mod filesystem;
+mod highlight;
+mod htpasswd;
+mod include;
+mod markdown;
+mod math;
 mod metadata;
+mod policy;
+mod references;
 mod render;
 mod responses;
 mod routes;
+mod sanitize;
+mod signed;
+mod throttle;
+mod titles;
+mod totp;
 
 use crate::article::RawArticle;
+use crate::audit::AuditLog;
+use crate::authentication::PendingRehashes;
+use crate::backlinks::BacklinkIndex;
+use crate::cache::RenderCache;
 use crate::auth::{Access, Session, User};
 use crate::config::*;
 pub use crate::metadata::Metadata;
 use crate::render::Renderer;
 pub use crate::responses::ErrorResponse;
-use crate::responses::TemplatedResponse;
+use crate::responses::{AuthenticationRequired, LastModified, TemplatedResponse};
+use crate::routes::{ACCOUNTS_FILE, AccountStore};
+use crate::sanitize::Sanitizer;
+use crate::throttle::LoginThrottle;
+use crate::titles::TitleIndex;
+use crate::totp::UsedCodes;
 use axum::extract::State;
 use axum::middleware::{Next, from_fn_with_state};
 use axum::response::{Html, Redirect};
@@ -23,6 +49,7 @@ use axum_core::body::Body;
 use axum_core::response::{IntoResponse, Response};
 use http::Request;
 use serde::Deserialize;
+use std::net::SocketAddr;
 use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use tera::Context;
@@ -32,6 +59,17 @@ use tower_http::{services::ServeDir, trace::TraceLayer};
 struct AppState {
     pub renderer: Arc<Renderer>,
     pub config: Arc<Config>,
+    pub throttle: Arc<LoginThrottle>,
+    pub audit: Arc<AuditLog>,
+    pub backlinks: Arc<BacklinkIndex>,
+    pub titles: Arc<TitleIndex>,
+    /// The stylesheet for highlighted code, generated from the configured themes.
+    pub highlight_css: Arc<String>,
+    pub sanitizer: Arc<Sanitizer>,
+    pub render_cache: Arc<RenderCache>,
+    pub used_codes: Arc<UsedCodes>,
+    pub pending_rehashes: Arc<PendingRehashes>,
+    pub accounts: Arc<AccountStore>,
 }
 
 #[tokio::main]
@@ -39,7 +77,7 @@ async fn main() {
     let mut config = match Config::from_file("config.toml").await {
         Ok(c) => c,
         Err(err) => {
-            eprintln!("Couldn't open `config.toml`: {}", err.to_string());
+            eprintln!("Couldn't open `config.toml`: {}", err);
             return;
         }
     };
@@ -51,66 +89,163 @@ async fn main() {
             "WARN: Empty or weak secret_key found in configuration. Using temp value; to make permanent, update config.toml with {key_string}"
         );
     }
+    if let Err(err) = config.password_hashing.params() {
+        eprintln!("Invalid [password_hashing] parameters in `config.toml`: {err}");
+        return;
+    }
+    let highlight_css = match highlight::theme_css(&config.highlighting) {
+        Ok(css) => css,
+        Err(err) => {
+            eprintln!("Invalid [highlighting] settings in `config.toml`: {err}");
+            return;
+        }
+    };
+    let sanitizer = match Sanitizer::new(&config.sanitization) {
+        Ok(sanitizer) => sanitizer,
+        Err(err) => {
+            eprintln!("Invalid [sanitization] settings in `config.toml`: {err}");
+            return;
+        }
+    };
     let config = Arc::new(config);
 
-    let mut account_config = match AccountConfig::from_file("accounts.toml").await {
+    let mut account_config = match AccountConfig::from_file(ACCOUNTS_FILE).await {
         Ok(c) => c,
         Err(err) => {
-            eprintln!("Couldn't open `accounts.toml`: {}", err.to_string());
+            eprintln!("Couldn't open `accounts.toml`: {}", err);
             return;
         }
     };
+    // Logins ignoring case couldn't tell these accounts apart.
+    if let Some((first, second)) = policy::find_case_conflict(&config.account_policy, &account_config) {
+        eprintln!(
+            "The accounts `{first}` and `{second}` in `accounts.toml` differ only in case. Rename one of them, or set `case_insensitive_usernames = false` in the [account_policy] section of `config.toml`."
+        );
+        return;
+    }
     if !account_config.validate_single_user_password() && config.auth_mode == auth::AuthenticationMode::Single {
-        let password = account_config.generate_single_user_password();
+        let password = account_config.generate_single_user_password(&config.password_hashing);
         eprintln!("WARN: Missing or invalid single-user password specified. Updating password to {password}");
 
-        if let Err(err) = account_config.write_to_file("accounts.toml").await {
+        if let Err(err) = account_config.write_to_file(ACCOUNTS_FILE).await {
             eprintln!("ERR: Failed to update accounts.toml. Authentication will not be possible.\n {err}")
         }
     }
 
+    // `smolwik import-htpasswd <file>` imports the accounts instead of starting the server.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let [command, path] = args.as_slice()
+        && command == "import-htpasswd"
+    {
+        import_htpasswd(path, &config, account_config).await;
+        return;
+    }
+
+    let titles = TitleIndex::default();
+    let backlinks = BacklinkIndex::build(&config.articles, &titles).await;
     let state = AppState {
         renderer: Renderer::new((*config).clone()).unwrap().into(),
         config: config.clone(),
+        throttle: LoginThrottle::new(config.login_throttling.clone()).into(),
+        audit: AuditLog::new(config.audit_log.clone()).into(),
+        backlinks: backlinks.into(),
+        titles: titles.into(),
+        highlight_css: highlight_css.into(),
+        sanitizer: sanitizer.into(),
+        render_cache: Default::default(),
+        used_codes: Default::default(),
+        pending_rehashes: Default::default(),
+        accounts: AccountStore::new(ACCOUNTS_FILE).into(),
     };
     
+    tracing_subscriber::fmt::init();
     let router = build_router(state, &config);
     
     // run it
     let listener = tokio::net::TcpListener::bind(&config.address).await.unwrap();
-    println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, router).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    println!("listening on {address}");
+    if config.cookies.secure && !address.ip().is_loopback() {
+        eprintln!(
+            "WARN: Session cookies are marked Secure, so browsers only keep logins over HTTPS or on localhost. If {address} is reached over plain HTTP, set `secure = false` in the [cookies] section of `config.toml`."
+        );
+    }
+    axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+/// Imports the accounts in the htpasswd file at the specified path into `accounts.toml`.
+async fn import_htpasswd(path: &str, config: &Config, mut account_config: AccountConfig) {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("Couldn't read `{path}`: {err}");
+            return;
+        }
+    };
+
+    let report = htpasswd::import(&contents, &config.account_policy, &mut account_config);
+    for skipped in &report.skipped {
+        eprintln!("WARN: {skipped}");
+    }
+    if report.imported.is_empty() {
+        println!("No accounts were imported.");
+        return;
+    }
+    if let Err(err) = account_config.write_to_file(ACCOUNTS_FILE).await {
+        eprintln!("ERR: Failed to update accounts.toml. No accounts were imported.\n {err}");
+        return;
+    }
+    for username in &report.imported {
+        println!("Imported {username}");
+    }
 }
 
 fn build_router(state: AppState, config: &Config) -> Router {
     let article_routes = routes::articles::router(state.clone());
     let auth_routes = routes::auth::router(state.clone());
+    let account_routes = routes::account::router(state.clone());
     let admin_routes = routes::admin::router(state.clone());
     let discovery_routes = routes::discovery::router(state.clone());
-
-    tracing_subscriber::fmt::init();
+    let invite_routes = routes::invite::router(state.clone());
+    let reset_routes = routes::reset::router(state.clone());
+    let user_routes = routes::users::router(state.clone());
 
     // build our application with a route
     Router::new()
+        .route("/assets/highlight.css", get(highlight_css_handler))
         .nest_service("/assets", ServeDir::new(&config.assets))
         .with_state(state.clone())
         .merge(article_routes)
         .merge(auth_routes)
+        .merge(account_routes)
+        .merge(invite_routes)
+        .merge(reset_routes)
         .merge(admin_routes)
         .merge(discovery_routes)
-        .layer(from_fn_with_state(state, template_middleware))
+        .merge(user_routes)
+        .layer(from_fn_with_state(state.clone(), template_middleware))
+        .layer(from_fn_with_state(state.clone(), authentication::authentication_middleware))
+        .layer(from_fn_with_state(state, csrf::csrf_middleware))
         .layer(TraceLayer::new_for_http())
 }
 
+async fn highlight_css_handler(State(state): State<AppState>) -> Response {
+    let headers = [(http::header::CONTENT_TYPE, "text/css; charset=utf-8")];
+    (headers, state.highlight_css.to_string()).into_response()
+}
+
 async fn template_middleware(
     State(state): State<AppState>,
     session: Session,
     request: Request<Body>,
     next: Next,
 ) -> Response {
+    let request_headers = request.headers().clone();
     let mut response = next.run(request).await;
     let extensions = response.extensions_mut();
-    if extensions.len() == 0 {
+    if extensions.is_empty() {
         // For routes that don't use extensions, just return the original response
         return response;
     }
@@ -124,18 +259,29 @@ async fn template_middleware(
     let context = extensions.remove::<Context>();
     let template = extensions.remove::<&'static str>().expect("String (Template) must be set.");
     let title = extensions.remove::<String>().unwrap_or(String::new());
+    let last_modified = extensions.remove::<LastModified>();
 
     if let Some(context) = context {
-        return match state
-            .renderer
-            .render_template_with_context(&session, &template, &title, context)
-        {
-            Ok(html) => Html(html).into_response().cookies_from(response),
-            Err(err) => render_error(&state, &session, err.into()).cookies_from(response),
+        let context = state.renderer.page_context(&session, &title, context);
+        // Pages with a modification time can be answered with `304 Not Modified` without rendering them.
+        let validators = last_modified.map(|LastModified(modified)| (cache::page_etag(template, &context), modified));
+        let page = match &validators {
+            Some((etag, _)) if cache::is_not_modified(etag, &request_headers) => {
+                http::StatusCode::NOT_MODIFIED.into_response()
+            }
+            _ => match state.renderer.render_page(template, &context) {
+                Ok(html) => Html(html).into_response(),
+                Err(err) => return render_error(&state, &session, err.into()).cookies_from(response),
+            },
         };
+        let page = match validators {
+            Some((etag, modified)) => cache::with_validators(page, &etag, modified),
+            None => page,
+        };
+        return page.cookies_from(response);
     }
 
-    match state.renderer.render_template(&session, &template, &title) {
+    match state.renderer.render_template(&session, template, &title) {
         Ok(html) => Html(html).into_response().cookies_from(response),
         Err(err) => render_error(&state, &session, err.into()).cookies_from(response),
     }
@@ -148,9 +294,20 @@ pub(crate) fn context(title: &str) -> Context {
     context
 }
 
+/// Gets the current time as the number of seconds since the unix epoch.
+pub(crate) fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System time should be after the unix epoch.")
+        .as_secs()
+}
+
 fn render_error(state: &AppState, session: &Session, error: ErrorResponse) -> Response {
-    let mut response = Html(state.renderer.render_error(&session, &error)).into_response();
+    let mut response = Html(state.renderer.render_error(session, &error)).into_response();
     *response.status_mut() = error.status_code;
+    if error == ErrorResponse::unauthenticated() {
+        response.extensions_mut().insert(AuthenticationRequired);
+    }
     response
 }
 
@@ -218,17 +375,445 @@ mod tests {
         let body = response.into_body().collect().await.unwrap();
         let body = String::try_from(Vec::from(body.to_bytes())).ok().whatever_context("Response body is not valid UTF-8")?;
         assert!(body.starts_with("<!DOCTYPE html>"));
-        assert!(body.contains("<h1>Welcome!</h1>"));
+        assert!(body.contains("<h1 id=\"welcome\">Welcome!"));
         assert!(body.contains("</html>"));
 
         Ok(())
     }
 
-    async fn setup(auth_mode: AuthenticationMode, access: Access) -> Result<Router, Whatever> {
+    #[tokio::test]
+    async fn get_index_not_modified() -> Result<(), Whatever> {
+        let router = setup(AuthenticationMode::Anonymous, Access::Authenticated).await?;
+        let response = router.clone().oneshot(Request::builder().uri("/").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get("ETag").whatever_context("ETag wasn't set")?.clone();
+        assert!(response.headers().get("Last-Modified").is_some());
+
+        // The page hasn't changed, so the browser can keep using its copy.
+        let request = Request::builder()
+            .uri("/")
+            .header("If-None-Match", &etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A copy of an older version of the page is replaced.
+        let request = Request::builder()
+            .uri("/")
+            .header("If-None-Match", "\"outdated\"")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The modification time alone doesn't cover everything else shown on the page.
+        let last_modified = response.headers().get("Last-Modified").whatever_context("Last-Modified wasn't set")?.clone();
+        let request = Request::builder()
+            .uri("/")
+            .header("If-Modified-Since", &last_modified)
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_index_proxy() -> Result<(), Whatever> {
+        use axum::extract::ConnectInfo;
+
+        let router = setup(AuthenticationMode::Proxy, Access::Authenticated).await?;
+        let request = |address: [u8; 4]| {
+            let mut request = Request::builder()
+                .uri("/")
+                .header("Remote-User", "alex")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::from((address, 8080))));
+            request
+        };
+
+        // The header is trusted from the proxy.
+        let response = router.clone().oneshot(request([127, 0, 0, 1])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap();
+        let body = String::try_from(Vec::from(body.to_bytes())).ok().whatever_context("Response body is not valid UTF-8")?;
+        assert!(body.contains("<span id=username>alex</span>"));
+
+        // But not from anywhere else.
+        let response = router.oneshot(request([192, 168, 1, 20])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap();
+        let body = String::try_from(Vec::from(body.to_bytes())).ok().whatever_context("Response body is not valid UTF-8")?;
+        assert!(!body.contains("alex"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tokens_need_account_with_proxy() -> Result<(), Whatever> {
+        use axum::extract::ConnectInfo;
+
+        let router = setup(AuthenticationMode::Proxy, Access::Authenticated).await?;
+        let request = |uri: &str| {
+            let mut request = Request::builder()
+                .uri(uri)
+                .header("Remote-User", "alex")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))));
+            request
+        };
+
+        // Proxy users without an account in `accounts.toml` have nowhere to keep tokens.
+        let response = router.clone().oneshot(request("/special:account")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap();
+        let body = String::try_from(Vec::from(body.to_bytes())).ok().whatever_context("Response body is not valid UTF-8")?;
+        assert!(!body.contains("/special:account:tokens"));
+
+        let response = router.oneshot(request("/special:account:tokens")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn basic_challenge_for_non_browsers() -> Result<(), Whatever> {
+        let router = setup(AuthenticationMode::Multi, Access::Authenticated).await?;
+        let request = |accept: &str| {
+            Request::builder()
+                .uri("/special:tree")
+                .header("Accept", accept)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(request("*/*")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("WWW-Authenticate").is_some_and(|value| value.as_bytes().starts_with(b"Basic ")));
+
+        // Browsers are sent to the login page instead.
+        let response = router.clone().oneshot(request("text/html,*/*")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("WWW-Authenticate").is_none());
+
+        // Rejected credentials of other kinds aren't answered with a Basic challenge.
+        let invalid_token = Request::builder()
+            .uri("/")
+            .header("Authorization", "Bearer invalid")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(invalid_token).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("WWW-Authenticate").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tokens_limited_to_articles() -> Result<(), Whatever> {
+        let router = setup(AuthenticationMode::Multi, Access::Anonymous).await?;
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Authorization", "Bearer smolwik_unknown")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for uri in ["/special:account:tokens", "/special:admin", "/special:admin:audit"] {
+            let response = router.clone().oneshot(request(uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        // Articles check the token itself.
+        let response = router.oneshot(request("/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn raw_markdown_with_token() -> Result<(), Whatever> {
+        use crate::article::RawArticle;
+        use crate::auth::ApiToken;
+        use crate::metadata::Metadata;
+
+        let test_dir = testdir!();
+        let mut state = setup_state(test_dir.clone(), AuthenticationMode::Multi, Access::Anonymous).await?;
+        let mut config = (*state.config).clone();
+        config.articles = test_dir.join("articles");
+        state.config = config.into();
+
+        let user = User::Account("demo".into());
+        let (token, secret) = ApiToken::generate("script".to_owned());
+        let mut account_config = state.accounts.load().await.unwrap();
+        account_config.tokens_mut(&user).whatever_context("Demo account is missing")?.push(token);
+        state.accounts.save(&account_config).await.unwrap();
+        for (name, view_access) in [("log", Access::Accounts(vec!["demo".into()])), ("diary", Access::Accounts(vec![]))] {
+            let article = RawArticle {
+                metadata: Metadata {
+                    title: name.to_owned(),
+                    view_access,
+                    edit_access: Access::Accounts(vec![]),
+                },
+                markdown: "- Fed the cat\n".to_owned(),
+            };
+            let path = state.config.articles.join(format!("{name}.md"));
+            article.write_to_path(&path, &format!("/{name}")).await.whatever_context("Failed to write article")?;
+        }
+        let config = state.config.clone();
+        let router = build_router(state, &config);
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Authorization", format!("Bearer {secret}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Only viewing is needed, even when asking for the edit page too.
+        let response = router.clone().oneshot(request("/log?raw&edit")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "text/markdown; charset=utf-8");
+        let body = response.into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "- Fed the cat\n");
+
+        let response = router.clone().oneshot(request("/diary?raw")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let anonymous = Request::builder().uri("/log?raw").body(Body::empty()).unwrap();
+        let response = router.oneshot(anonymous).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn basic_credentials_ignored_without_passwords() -> Result<(), Whatever> {
+        let router = setup(AuthenticationMode::Anonymous, Access::Authenticated).await?;
+        let request = Request::builder()
+            .uri("/")
+            .header("Authorization", "Basic YWxleDpodW50ZXIy")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn legacy_session_cookie_migration() -> Result<(), Whatever> {
+        use axum_extra::extract::SignedCookieJar;
+        use axum_extra::extract::cookie::{Cookie, Key};
+
+        let router = setup(AuthenticationMode::Single, Access::Authenticated).await?;
+        let request = |cookie: &str| {
+            Request::builder()
+                .uri("/")
+                .header("Cookie", cookie)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let session_cookie = |response: &Response| {
+            response
+                .headers()
+                .get_all("Set-Cookie")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .find(|value| value.starts_with("session="))
+                .map(|value| value.split(';').next().unwrap().to_owned())
+        };
+
+        // Earlier versions only signed the session cookie.
+        let value = serde_json::to_string(&Session::new(User::SingleUser)).unwrap();
+        let legacy = SignedCookieJar::new(Key::from(&[0u8; 64])).add(Cookie::new("session", value));
+        let legacy = session_cookie(&(legacy, ()).into_response()).whatever_context("Legacy cookie wasn't set")?;
+
+        let response = router.clone().oneshot(request(&legacy)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let encrypted = session_cookie(&response).whatever_context("Legacy cookie wasn't replaced")?;
+        assert!(!encrypted.contains("SingleUser"));
+
+        // The replacement is used from then on.
+        let response = router.oneshot(request(&encrypted)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(session_cookie(&response).is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn totp_enrollment_keeps_existing_secret() -> Result<(), Whatever> {
+        use axum_extra::extract::PrivateCookieJar;
+        use axum_extra::extract::cookie::Key;
+        use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+        use totp_rs::{Algorithm, Secret, TOTP};
+
+        let state = setup_state(testdir!(), AuthenticationMode::Multi, Access::Anonymous).await?;
+        let user = User::Account("demo".into());
+        let enrolled = totp::generate_secret();
+        let mut account_config = state.accounts.load().await.unwrap();
+        routes::account::set_totp_secret(&mut account_config, &user, Some(enrolled.clone())).unwrap();
+        state.accounts.save(&account_config).await.unwrap();
+        let accounts = state.accounts.clone();
+        let config = state.config.clone();
+        let router = build_router(state, &config);
+
+        let session = Session::new(user.clone());
+        let jar = PrivateCookieJar::new(Key::from(&[0u8; 64])).add(session.cookie(&config.cookies));
+        let response = (jar, ()).into_response();
+        let cookie = response.headers().get("Set-Cookie").whatever_context("Session cookie wasn't set")?;
+        let cookie = cookie.to_str().unwrap().split(';').next().unwrap().to_owned();
+
+        // A stolen session can't swap in its own authenticator to get around the code needed to disable it.
+        let secret = totp::generate_secret();
+        let bytes = Secret::Encoded(secret.clone()).to_bytes().unwrap();
+        let code = TOTP::new_unchecked(Algorithm::SHA1, 6, 1, 30, bytes, None, String::new()).generate_current().unwrap();
+        let session_id = utf8_percent_encode(session.id.as_deref().unwrap(), NON_ALPHANUMERIC);
+        let request = Request::builder()
+            .method("POST")
+            .uri("/special:account:totp")
+            .header("Cookie", &cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(format!("secret={secret}&code={code}&session_id={session_id}")))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(accounts.load().await.unwrap().totp_secret(&user), Some(enrolled.as_str()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn profile_hides_articles_and_accounts() -> Result<(), Whatever> {
+        use crate::audit::{AuditAction, AuditEvent};
+        use crate::auth::Username;
+        use crate::metadata::Metadata;
+
+        let state = setup_state(testdir!(), AuthenticationMode::Multi, Access::Anonymous).await?;
+        let metadata = |title: &str, view_access: Access| Metadata {
+            title: title.to_owned(),
+            view_access,
+            ..Default::default()
+        };
+        state.titles.insert("/garden", &metadata("Garden Plans", Access::Anonymous));
+        state.titles.insert("/diary", &metadata("Secret Diary", Access::Authenticated));
+        for target in ["/garden", "/diary"] {
+            state.audit.record(AuditEvent {
+                time: 1,
+                user: User::Account(Username::from("demo".to_owned())),
+                address: None,
+                action: AuditAction::ArticleUpdate,
+                target: Some(target.to_owned()),
+                details: None,
+            }).await;
+        }
+        let config = state.config.clone();
+        let router = build_router(state, &config);
+        let get = async |uri: &str| -> Result<(StatusCode, String), Whatever> {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap();
+            let body = String::try_from(Vec::from(body.to_bytes())).ok().whatever_context("Response body is not valid UTF-8")?;
+            Ok((status, body))
+        };
+
+        // Contributions to articles the viewer can't see are left out.
+        let (status, body) = get("/special:user/demo").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Garden Plans"));
+        assert!(!body.contains("Secret Diary"));
+        assert!(!body.contains("/diary"));
+
+        // Names without an account look the same as accounts without contributions.
+        let (status, body) = get("/special:user/DEMO2").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<h1>DEMO2</h1>"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn backlinks_hidden_with_article() -> Result<(), Whatever> {
+        use crate::metadata::Metadata;
+
+        let state = setup_state(testdir!(), AuthenticationMode::Multi, Access::Anonymous).await?;
+        let metadata = Metadata {
+            title: "Secret Diary".to_owned(),
+            view_access: Access::Authenticated,
+            ..Default::default()
+        };
+        state.titles.insert("/diary", &metadata);
+        let config = state.config.clone();
+        let router = build_router(state, &config);
+        let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        // Links to an article are only listed for those who may view it.
+        let response = router.clone().oneshot(request("/special:backlinks?path=diary")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = router.oneshot(request("/special:backlinks?path=index")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_updates_article_visibility() -> Result<(), Whatever> {
+        use crate::article::RawArticle;
+        use crate::auth::Username;
+        use crate::metadata::Metadata;
+        use crate::routes::admin::rewrite_references;
+
         let test_dir = testdir!();
+        let mut state = setup_state(test_dir.clone(), AuthenticationMode::Multi, Access::Anonymous).await?;
+        let mut config = (*state.config).clone();
+        config.articles = test_dir.join("articles");
+        state.config = config.into();
+
+        let article = RawArticle {
+            metadata: Metadata {
+                title: "Secret Diary".to_owned(),
+                view_access: Access::Accounts(vec!["alex".into()]),
+                ..Default::default()
+            },
+            markdown: String::new(),
+        };
+        article.write_to_path(&state.config.articles.join("diary.md"), "/diary").await.whatever_context("Failed to write article")?;
+        state.backlinks.update(&state.titles, "/diary", &article);
+        let visible_to = |username: &str| {
+            let user = User::Account(Username::from(username.to_owned()));
+            state.titles.get("/diary").is_some_and(|article| article.is_visible_to(Some(&user)))
+        };
+        assert!(visible_to("alex"));
+
+        // The renamed account keeps seeing its articles, and the old name doesn't.
+        let rewritten = rewrite_references(&state, &"alex".into(), Some(&"sam".into())).await.unwrap();
+        assert_eq!(rewritten, vec!["/diary".to_owned()]);
+        assert!(visible_to("sam"));
+        assert!(!visible_to("alex"));
+
+        // A new account reusing a deleted name doesn't inherit its access.
+        rewrite_references(&state, &"sam".into(), None).await.unwrap();
+        assert!(!visible_to("sam"));
+
+        Ok(())
+    }
+
+    async fn setup(auth_mode: AuthenticationMode, access: Access) -> Result<Router, Whatever> {
+        let state = setup_state(testdir!(), auth_mode, access).await?;
+        let config = state.config.clone();
+        Ok(build_router(state, &config))
+    }
+
+    async fn setup_state(test_dir: PathBuf, auth_mode: AuthenticationMode, access: Access) -> Result<AppState, Whatever> {
         let articles_path = test_dir.join("articles");
         let assets_path = test_dir.join("assets");
         let templates_path = test_dir.join("templates");
+        let accounts_path = test_dir.join("accounts.toml");
 
         fs::create_dir(&articles_path).await.whatever_context("Failed to create articles directory")?;
         fs::create_dir(&assets_path).await.whatever_context("Failed to create assets directory")?;
@@ -236,6 +821,7 @@ mod tests {
         fs::copy("articles/index.md", &articles_path.join("index.md")).await.whatever_context("Failed to copy index article.")?;
         fs::copy("templates/base.tera", &templates_path.join("base.tera")).await.whatever_context("Failed to copy base template.")?;
         fs::copy("templates/article.tera", &templates_path.join("article.tera")).await.whatever_context("Failed to copy article template.")?;
+        fs::copy(ACCOUNTS_FILE, &accounts_path).await.whatever_context("Failed to copy accounts.")?;
 
         // The config is only read once at start up, so we don't need to copy it to the temp directory.
         let config = Config {
@@ -248,14 +834,35 @@ mod tests {
             articles: PathBuf::from("articles/"),
             assets: PathBuf::from("assets/"),
             templates: "templates/**/*".to_string(),
+            audit_log: test_dir.join("audit.jsonl"),
+            login_throttling: Default::default(),
+            proxy_auth: Default::default(),
+            account_policy: Default::default(),
+            password_hashing: Default::default(),
+            cookies: Default::default(),
+            csrf: Default::default(),
+            highlighting: Default::default(),
+            sanitization: Default::default(),
         };
 
         let config = Arc::new(config);
+        let titles = TitleIndex::default();
+        let backlinks = BacklinkIndex::build(&config.articles, &titles).await;
         let state = AppState {
             renderer: Renderer::new((*config).clone()).unwrap().into(),
             config: config.clone(),
+            throttle: LoginThrottle::new(config.login_throttling.clone()).into(),
+            audit: AuditLog::new(config.audit_log.clone()).into(),
+            backlinks: backlinks.into(),
+            titles: titles.into(),
+            highlight_css: highlight::theme_css(&config.highlighting).unwrap().into(),
+            sanitizer: Sanitizer::new(&config.sanitization).unwrap().into(),
+            render_cache: Default::default(),
+            used_codes: Default::default(),
+            pending_rehashes: Default::default(),
+            accounts: AccountStore::new(accounts_path).into(),
         };
 
-        Ok(build_router(state, &config))
+        Ok(state)
     }
 }
@@ -0,0 +1,691 @@
+use crate::article::RawArticle;
+use crate::auth::{Access, User, Username};
+use crate::cache::Source;
+use crate::config::{AccountConfig, AccountPolicyConfig};
+use crate::highlight;
+use crate::include::{self, Includes, Nesting};
+use crate::math;
+use crate::policy;
+use crate::sanitize::Sanitizer;
+use crate::titles::{IndexedArticle, TitleIndex};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, TextMergeStream};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::path::Path;
+use tera::escape_html;
+
+/// Characters escaped in the query parameters of links to create missing articles.
+const QUERY_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'/').remove(b'-').remove(b'_').remove(b'.');
+
+/// Everything besides the markdown itself needed to render an article.
+pub struct RenderContext<'a> {
+    pub mentions: Mentions<'a>,
+    pub wiki_links: WikiLinks,
+    /// The articles included with `{{< include "/path" >}}`, already rendered.
+    pub includes: Includes,
+    /// The sanitizer for the rendered HTML, or [None] if the article's editors are trusted.
+    pub sanitizer: Option<&'a Sanitizer>,
+    /// The heading IDs already used on the page by included articles, which the article's headings
+    /// mustn't reuse.
+    pub heading_ids: HashSet<String>,
+}
+
+/// Renders articles for a particular viewer, resolving the other articles they refer to.
+pub struct ArticleRenderer<'a> {
+    pub articles: &'a Path,
+    pub titles: &'a TitleIndex,
+    pub policy: &'a AccountPolicyConfig,
+    pub accounts: &'a AccountConfig,
+    pub sanitizer: &'a Sanitizer,
+    /// The viewer, who only sees included and linked articles they're allowed to view.
+    pub user: &'a User,
+}
+
+impl ArticleRenderer<'_> {
+    /// Renders the article at the specified URL.
+    pub async fn render(&self, url: &str, article: &RawArticle) -> RenderedMarkdown {
+        self.render_nested(url, article, &mut Nesting::new(url)).await
+    }
+
+    /// Renders an article, which may be included in the articles being rendered in `nesting`.
+    pub async fn render_nested(&self, url: &str, article: &RawArticle, nesting: &mut Nesting) -> RenderedMarkdown {
+        let includes = Includes::resolve(self, url, &article.markdown, nesting).await;
+        let context = RenderContext {
+            mentions: Mentions::new(self.policy, self.accounts),
+            wiki_links: WikiLinks::resolve(self.titles, Some(self.user), url, &article.markdown),
+            includes,
+            sanitizer: self.sanitizer.for_article(&article.metadata.edit_access),
+            heading_ids: nesting.heading_ids().clone(),
+        };
+        let rendered = render(&article.markdown, &context);
+        nesting.reserve_heading_ids(&rendered.toc);
+        rendered
+    }
+}
+
+/// Resolves `@username` mentions to the accounts they refer to.
+pub struct Mentions<'a> {
+    policy: &'a AccountPolicyConfig,
+    accounts: &'a AccountConfig,
+}
+
+impl<'a> Mentions<'a> {
+    pub fn new(policy: &'a AccountPolicyConfig, accounts: &'a AccountConfig) -> Mentions<'a> {
+        Mentions { policy, accounts }
+    }
+
+    /// Finds the account with the specified username, ignoring case if the account policy does.
+    pub fn resolve(&self, name: &str) -> Option<&'a Username> {
+        let name = Username::from(name);
+        self.accounts
+            .accounts
+            .iter()
+            .map(|acc| &acc.username)
+            .find(|username| policy::is_same_username(self.policy, username, &name))
+    }
+
+    fn is_username_char(&self, c: char) -> bool {
+        c.is_ascii_alphanumeric() || self.policy.username_characters.contains(c)
+    }
+
+    /// Splits text into plain text and links to the profiles of any mentioned accounts.
+    fn link<'t>(&self, text: &str, events: &mut Vec<Event<'t>>) {
+        let mut plain_start = 0;
+        let mut previous = None;
+        let mut chars = text.char_indices().peekable();
+        while let Some((at, c)) = chars.next() {
+            // Mentions must start a word, so that email addresses aren't linked.
+            let starts_word = previous.is_none_or(|p: char| !p.is_alphanumeric() && !self.is_username_char(p));
+            previous = Some(c);
+            if c != '@' || !starts_word {
+                continue;
+            }
+
+            let name_start = at + 1;
+            let mut name_end = name_start;
+            while let Some(&(i, c)) = chars.peek()
+                && self.is_username_char(c)
+            {
+                name_end = i + c.len_utf8();
+                previous = Some(c);
+                chars.next();
+            }
+
+            // Punctuation ending a sentence isn't part of the username, unless an account has it.
+            let name = &text[name_start..name_end];
+            let trimmed = name.trim_end_matches(|c: char| !c.is_ascii_alphanumeric());
+            let (name, username) = match (self.resolve(name), self.resolve(trimmed)) {
+                (Some(username), _) => (name, username),
+                (None, Some(username)) => (trimmed, username),
+                (None, None) => continue,
+            };
+            let name_end = name_start + name.len();
+
+            if plain_start < at {
+                events.push(Event::Text(CowStr::from(text[plain_start..at].to_owned())));
+            }
+            events.push(Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url: CowStr::from(format!("/special:user/{username}")),
+                title: CowStr::from(""),
+                id: CowStr::from(""),
+            }));
+            events.push(Event::Text(CowStr::from(text[at..name_end].to_owned())));
+            events.push(Event::End(TagEnd::Link));
+            plain_start = name_end;
+        }
+        if plain_start < text.len() {
+            events.push(Event::Text(CowStr::from(text[plain_start..].to_owned())));
+        }
+    }
+}
+
+/// Where a `[[wiki link]]` leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WikiLink {
+    /// The URL of an existing article.
+    Article(String),
+    /// An article that doesn't exist yet, linking to the page to create it.
+    Missing { path: String, title: String },
+}
+
+/// The targets of the `[[wiki links]]` in an article, resolved ahead of rendering.
+#[derive(Debug, Default)]
+pub struct WikiLinks {
+    links: HashMap<String, WikiLink>,
+    /// Whether any link leads to an article not everyone may see, so that the links differ between viewers.
+    restricted: bool,
+}
+
+impl WikiLinks {
+    /// Resolves every wiki link in the markdown of the article at `url`. Links are first resolved as
+    /// paths relative to the article's directory, then the article root, then as the title of an article.
+    /// Names are also tried as paths in their slug form, so `[[Ice Maker]]` can link to `ice-maker`.
+    /// Articles the viewer can't see are treated as missing, so that the links don't reveal whether
+    /// private articles exist. [None] resolves links regardless of access, as when indexing them.
+    pub fn resolve(titles: &TitleIndex, viewer: Option<&User>, url: &str, markdown: &str) -> WikiLinks {
+        let targets = wiki_link_targets(markdown);
+        WikiLinks::resolve_targets(titles, viewer, url, targets.iter().map(|target| target.as_ref()))
+    }
+
+    /// Resolves the destinations of wiki links found earlier with [wiki_link_targets], in the same way
+    /// as [WikiLinks::resolve].
+    pub fn resolve_targets<'t>(
+        titles: &TitleIndex,
+        viewer: Option<&User>,
+        url: &str,
+        targets: impl IntoIterator<Item = &'t str>,
+    ) -> WikiLinks {
+        let directory = directory(url);
+        let mut wiki_links = WikiLinks::default();
+        for dest_url in targets {
+            let (target, fragment) = split_fragment(dest_url);
+            // Links resolving to a public article for everyone resolve the same for every viewer.
+            let mut article = find_article(titles, directory, target, None);
+            if let Some(found) = &article
+                && !matches!(found.view_access, Access::Anonymous)
+            {
+                wiki_links.restricted = true;
+                if viewer.is_some() {
+                    article = find_article(titles, directory, target, viewer);
+                }
+            }
+            let link = match article {
+                Some(article) => WikiLink::Article(match fragment {
+                    Some(fragment) => format!("{}#{fragment}", article.url),
+                    None => article.url,
+                }),
+                None => missing_link(directory, target),
+            };
+            wiki_links.links.insert(dest_url.to_string(), link);
+        }
+        wiki_links
+    }
+
+    pub fn get(&self, dest_url: &str) -> Option<&WikiLink> {
+        self.links.get(dest_url)
+    }
+}
+
+/// Gets the destinations of every wiki link in the markdown, without duplicates. External links are
+/// left as they are, so aren't included.
+pub fn wiki_link_targets(markdown: &str) -> Vec<CowStr<'_>> {
+    let mut targets = Vec::new();
+    for event in Parser::new_ext(markdown, Options::all()) {
+        if let Event::Start(Tag::Link {
+            link_type: LinkType::WikiLink { .. },
+            dest_url,
+            ..
+        }) = event
+            && !dest_url.contains("://")
+            && !targets.contains(&dest_url)
+        {
+            targets.push(dest_url);
+        }
+    }
+    targets
+}
+
+fn split_fragment(dest_url: &str) -> (&str, Option<&str>) {
+    match dest_url.split_once('#') {
+        Some((target, fragment)) => (target.trim(), Some(fragment)),
+        None => (dest_url.trim(), None),
+    }
+}
+
+/// Gets the directory containing the article at the specified URL, without a trailing slash.
+pub fn directory(url: &str) -> &str {
+    url.rsplit_once('/').map_or("", |(directory, _)| directory)
+}
+
+/// Finds the article a wiki link refers to that the viewer may see, by its path or its title.
+fn find_article(titles: &TitleIndex, directory: &str, target: &str, viewer: Option<&User>) -> Option<IndexedArticle> {
+    find_by_path(titles, directory, target, viewer).or_else(|| titles.find_title(target, viewer))
+}
+
+/// Finds the article at the specified path, relative to the directory or the article root.
+fn find_by_path(titles: &TitleIndex, directory: &str, target: &str, viewer: Option<&User>) -> Option<IndexedArticle> {
+    let relative = |path: &str| match path.strip_prefix('/') {
+        Some(absolute) => vec![absolute.to_owned()],
+        None => vec![format!("{directory}/{path}"), path.to_owned()],
+    };
+    let mut candidates = relative(target);
+    candidates.extend(relative(&slug(target)));
+    candidates
+        .iter()
+        .filter_map(|candidate| titles.find_path(candidate))
+        .find(|article| article.is_visible_to(viewer))
+}
+
+/// Links a missing article to the page to create it, in the linking article's directory unless
+/// the link is to a path.
+fn missing_link(directory: &str, target: &str) -> WikiLink {
+    let (path, title) = match target.rsplit_once('/') {
+        Some((_, name)) if target.starts_with('/') => (target.to_owned(), name),
+        Some((_, name)) => (format!("{directory}/{target}"), name),
+        None => (format!("{directory}/{}", slug(target)), target),
+    };
+    WikiLink::Missing {
+        path: path.trim_start_matches('/').to_owned(),
+        title: title.to_owned(),
+    }
+}
+
+/// Converts an article name to the form used in paths, such as `ice-maker` for `Ice Maker`.
+fn slug(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_alphanumeric() || c == '/' || c == '.' {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// A heading in an article's table of contents, along with the headings below it.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    /// The ID of the heading, used to link to it.
+    pub id: String,
+    pub title: String,
+    pub level: u8,
+    pub children: Vec<TocEntry>,
+}
+
+/// The HTML rendered from an article's markdown, along with its table of contents.
+#[derive(Debug, Clone, Default)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub toc: Vec<TocEntry>,
+    /// Whether the table of contents was placed in the article with a `[TOC]` marker.
+    pub toc_inline: bool,
+    /// The files of the articles included in it.
+    pub included: Vec<Source>,
+    /// Whether it links to articles that not everyone may see.
+    pub restricted_links: bool,
+}
+
+/// Renders CommonMark to HTML, linking `@username` mentions of existing accounts to their profiles
+/// and `[[wiki links]]` to the articles they refer to, and placing included articles. Math is
+/// converted to MathML, fenced code blocks are highlighted, and headings are given IDs and
+/// permalinks, and collected into a table of contents. Unsafe HTML is removed unless the context
+/// says the article's editors are trusted.
+pub fn render(markdown: &str, context: &RenderContext) -> RenderedMarkdown {
+    let mut events = link(markdown, context);
+    place_includes(&mut events, &context.includes);
+    render_math(&mut events);
+    highlight_code(&mut events);
+    let toc = identify_headings(&mut events, &context.heading_ids);
+    let toc_inline = place_toc(&mut events, &toc);
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    if let Some(sanitizer) = context.sanitizer {
+        html = sanitizer.clean(&html);
+    }
+    RenderedMarkdown {
+        html,
+        toc,
+        toc_inline,
+        included: context.includes.sources().to_vec(),
+        restricted_links: context.wiki_links.restricted,
+    }
+}
+
+/// Parses the markdown, replacing mentions and wiki links with links to the pages they refer to.
+fn link<'a>(markdown: &'a str, context: &RenderContext) -> Vec<Event<'a>> {
+    let parser = TextMergeStream::new(Parser::new_ext(markdown, Options::all()));
+
+    let mut events = Vec::new();
+    // Mentions aren't linked inside code, or inside other links.
+    let mut code_depth = 0usize;
+    let mut link_depth = 0usize;
+    // The lines of the raw HTML block being read, joined since a tag may span several lines.
+    let mut html_block = String::new();
+    for event in parser {
+        match &event {
+            Event::Html(html) if context.sanitizer.is_some() => {
+                html_block.push_str(html);
+                continue;
+            }
+            Event::End(TagEnd::HtmlBlock) if let Some(sanitizer) = context.sanitizer => {
+                events.push(Event::Html(CowStr::from(sanitizer.remove_reserved_attributes(&html_block))));
+                html_block.clear();
+            }
+            Event::InlineHtml(html) if let Some(sanitizer) = context.sanitizer => {
+                events.push(Event::InlineHtml(CowStr::from(sanitizer.remove_reserved_attributes(html))));
+                continue;
+            }
+            Event::Start(Tag::CodeBlock(_)) => code_depth += 1,
+            Event::End(TagEnd::CodeBlock) => code_depth -= 1,
+            Event::Start(Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                dest_url,
+                title,
+                id,
+            }) => {
+                link_depth += 1;
+                match context.wiki_links.get(dest_url) {
+                    Some(WikiLink::Article(url)) => events.push(Event::Start(Tag::Link {
+                        link_type: LinkType::Inline,
+                        dest_url: CowStr::from(url.clone()),
+                        title: title.clone(),
+                        id: id.clone(),
+                    })),
+                    // Links to missing articles are styled differently, which needs a class.
+                    Some(WikiLink::Missing { path, title }) => events.push(Event::InlineHtml(CowStr::from(format!(
+                        "<a class=missing href=\"/special:create?path={}&amp;title={}\">",
+                        utf8_percent_encode(path, QUERY_ESCAPES),
+                        utf8_percent_encode(title, QUERY_ESCAPES),
+                    )))),
+                    None => events.push(event),
+                }
+                continue;
+            }
+            Event::Start(Tag::Link { .. } | Tag::Image { .. }) => link_depth += 1,
+            Event::End(TagEnd::Link | TagEnd::Image) => link_depth -= 1,
+            Event::Text(text) if code_depth == 0 && link_depth == 0 && text.contains('@') => {
+                context.mentions.link(text, &mut events);
+                continue;
+            }
+            _ => {}
+        }
+        events.push(event);
+    }
+    events
+}
+
+/// Replaces paragraphs containing only an include directive with the included article.
+fn place_includes(events: &mut Vec<Event>, includes: &Includes) {
+    let mut index = 0;
+    while index + 2 < events.len() {
+        if let [Event::Start(Tag::Paragraph), Event::Text(text), Event::End(TagEnd::Paragraph)] = &events[index..index + 3]
+            && let Some(target) = include::parse_directive(text)
+            && let Some(include) = includes.get(target)
+        {
+            let html = include.html(target);
+            events.splice(index..index + 3, [Event::Html(CowStr::from(html))]);
+        }
+        index += 1;
+    }
+}
+
+/// Replaces `$inline$` and `$$display$$` math with MathML.
+fn render_math(events: &mut [Event]) {
+    for event in events {
+        let mathml = match event {
+            Event::InlineMath(tex) => math::render(tex, false),
+            Event::DisplayMath(tex) => math::render(tex, true),
+            _ => continue,
+        };
+        *event = Event::InlineHtml(CowStr::from(mathml));
+    }
+}
+
+/// Replaces fenced code blocks in a recognized language with highlighted HTML.
+fn highlight_code(events: &mut Vec<Event>) {
+    let mut index = 0;
+    while index + 2 < events.len() {
+        // Adjacent text was merged while parsing, so the code of a block is always a single event.
+        if let [Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))), Event::Text(code), Event::End(TagEnd::CodeBlock)] =
+            &events[index..index + 3]
+            && let Some(html) = highlight::highlight_block(info, code)
+        {
+            events.splice(index..index + 3, [Event::Html(CowStr::from(html))]);
+        }
+        index += 1;
+    }
+}
+
+/// Gives every heading an ID, unless set with `{#id}`, and adds a permalink to it. IDs in `used_ids`
+/// aren't reused. Returns the headings as a table of contents.
+fn identify_headings(events: &mut Vec<Event>, used_ids: &HashSet<String>) -> Vec<TocEntry> {
+    let mut headings = Vec::new();
+    let mut used_ids = used_ids.clone();
+    let mut index = 0;
+    while index < events.len() {
+        let Event::Start(Tag::Heading { level, id, .. }) = &events[index] else {
+            index += 1;
+            continue;
+        };
+        let level = *level as u8;
+        let explicit_id = id.clone();
+        let end = index + events[index..].iter().position(|e| matches!(e, Event::End(TagEnd::Heading(_)))).unwrap_or(0);
+        let title = events[index..end]
+            .iter()
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect::<String>();
+
+        // Duplicate headings are numbered like `notes`, `notes-1`, `notes-2` to keep their IDs unique.
+        let base = match explicit_id {
+            Some(id) => id.to_string(),
+            None => match slug(&title).replace('/', "-") {
+                base if base.is_empty() => "section".to_owned(),
+                base => base,
+            },
+        };
+        let mut id = base.clone();
+        let mut number = 0;
+        while used_ids.contains(&id) {
+            number += 1;
+            id = format!("{base}-{number}");
+        }
+        used_ids.insert(id.clone());
+
+        if let Event::Start(Tag::Heading { id: heading_id, .. }) = &mut events[index] {
+            *heading_id = Some(CowStr::from(id.clone()));
+        }
+        let permalink = format!("<a class=permalink href=\"#{}\" aria-label=\"Permalink\">#</a>", escape_html(&id));
+        events.insert(end, Event::InlineHtml(CowStr::from(permalink)));
+        headings.push((level, id, title));
+        index = end + 1;
+    }
+    nest_headings(&mut headings.into_iter().peekable(), 0)
+}
+
+/// Nests each heading under the closest preceding heading with a higher level.
+fn nest_headings(headings: &mut Peekable<impl Iterator<Item = (u8, String, String)>>, parent: u8) -> Vec<TocEntry> {
+    let mut entries = Vec::new();
+    while let Some((level, id, title)) = headings.next_if(|(level, _, _)| *level > parent) {
+        let children = nest_headings(headings, level);
+        entries.push(TocEntry {
+            id,
+            title,
+            level,
+            children,
+        });
+    }
+    entries
+}
+
+/// Replaces paragraphs containing only `[TOC]` with the table of contents. Returns true if any were replaced.
+fn place_toc(events: &mut Vec<Event>, toc: &[TocEntry]) -> bool {
+    let mut placed = false;
+    let mut index = 0;
+    while index + 2 < events.len() {
+        if let [Event::Start(Tag::Paragraph), Event::Text(text), Event::End(TagEnd::Paragraph)] = &events[index..index + 3]
+            && text.trim() == "[TOC]"
+        {
+            let mut html = String::from("<nav class=toc>");
+            push_toc_html(&mut html, toc);
+            html.push_str("</nav>\n");
+            events.splice(index..index + 3, [Event::Html(CowStr::from(html))]);
+            placed = true;
+        }
+        index += 1;
+    }
+    placed
+}
+
+fn push_toc_html(html: &mut String, entries: &[TocEntry]) {
+    if entries.is_empty() {
+        return;
+    }
+    html.push_str("<ul>");
+    for entry in entries {
+        html.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            escape_html(&entry.id),
+            escape_html(&entry.title)
+        ));
+        push_toc_html(html, &entry.children);
+        html.push_str("</li>");
+    }
+    html.push_str("</ul>");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{Access, Account};
+    use crate::config::PasswordHashingConfig;
+    use crate::markdown::*;
+    use crate::metadata::Metadata;
+
+    fn accounts(names: &[&str]) -> AccountConfig {
+        let hashing = PasswordHashingConfig::default();
+        AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: names.iter().map(|name| Account::new(Username::from(*name), "", &hashing)).collect(),
+            used_invites: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn mentions() {
+        let policy = AccountPolicyConfig::default();
+        let accounts = accounts(&["alex", "sam.j"]);
+        let context = RenderContext {
+            mentions: Mentions::new(&policy, &accounts),
+            wiki_links: WikiLinks::default(),
+            includes: Includes::default(),
+            sanitizer: None,
+            heading_ids: HashSet::new(),
+        };
+        let render = |markdown: &str| render(markdown, &context).html;
+
+        assert_eq!(
+            render("Ask @alex or @Sam.J."),
+            "<p>Ask <a href=\"/special:user/alex\">@alex</a> or <a href=\"/special:user/sam.j\">@Sam.J</a>.</p>\n"
+        );
+        assert_eq!(render("Thanks @alex."), "<p>Thanks <a href=\"/special:user/alex\">@alex</a>.</p>\n");
+        assert_eq!(render("@nobody and alex@example.com"), "<p>@nobody and alex@example.com</p>\n");
+        assert_eq!(render("`@alex`"), "<p><code>@alex</code></p>\n");
+        assert_eq!(render("[@alex](/alex)"), "<p><a href=\"/alex\">@alex</a></p>\n");
+    }
+
+    #[test]
+    fn wiki_links() {
+        let titles = TitleIndex::default();
+        for (url, title, view_access) in [
+            ("/house/index", "House", Access::Anonymous),
+            ("/house/ice-maker", "Ice Maker", Access::Anonymous),
+            ("/recipes/roux", "Basic Roux", Access::Anonymous),
+            ("/private/safe", "Safe Combination", Access::Authenticated),
+        ] {
+            let metadata = Metadata {
+                title: title.to_owned(),
+                edit_access: Access::Authenticated,
+                view_access,
+            };
+            titles.insert(url, &metadata);
+        }
+
+        let markdown = "[[ice-maker]] [[Ice Maker#Filter|filter]] [[/recipes/roux]] [[basic roux]] [[house]] [[Dish Washer]] \
+            [[/garden/shed|shed]]";
+        let links = WikiLinks::resolve(&titles, Some(&User::Anonymous), "/house/index", markdown);
+        let article = |url: &str| Some(WikiLink::Article(url.to_owned()));
+        assert!(!links.restricted);
+        assert_eq!(links.get("ice-maker").cloned(), article("/house/ice-maker"));
+        assert_eq!(links.get("Ice Maker#Filter").cloned(), article("/house/ice-maker#Filter"));
+        assert_eq!(links.get("/recipes/roux").cloned(), article("/recipes/roux"));
+        assert_eq!(links.get("basic roux").cloned(), article("/recipes/roux"));
+        assert_eq!(links.get("house").cloned(), article("/house/index"));
+        assert_eq!(
+            links.get("Dish Washer").cloned(),
+            Some(WikiLink::Missing {
+                path: "house/dish-washer".to_owned(),
+                title: "Dish Washer".to_owned()
+            })
+        );
+
+        let policy = AccountPolicyConfig::default();
+        let accounts = accounts(&[]);
+        let context = RenderContext {
+            mentions: Mentions::new(&policy, &accounts),
+            wiki_links: links,
+            includes: Includes::default(),
+            sanitizer: None,
+            heading_ids: HashSet::new(),
+        };
+        assert_eq!(
+            render("[[/recipes/roux]] [[/garden/shed|shed]]", &context).html,
+            "<p><a href=\"/recipes/roux\">/recipes/roux</a> \
+            <a class=missing href=\"/special:create?path=garden/shed&amp;title=shed\">shed</a></p>\n"
+        );
+
+        // Private articles look the same as missing ones to viewers who can't see them.
+        let markdown = "[[Safe Combination]] [[/private/safe]] [[/private/vault]]";
+        let links = WikiLinks::resolve(&titles, Some(&User::Anonymous), "/house/index", markdown);
+        assert!(links.restricted);
+        let missing = |path: &str, title: &str| {
+            Some(WikiLink::Missing {
+                path: path.to_owned(),
+                title: title.to_owned(),
+            })
+        };
+        assert_eq!(links.get("Safe Combination").cloned(), missing("house/safe-combination", "Safe Combination"));
+        assert_eq!(links.get("/private/safe").cloned(), missing("private/safe", "safe"));
+        assert_eq!(links.get("/private/vault").cloned(), missing("private/vault", "vault"));
+        let links = WikiLinks::resolve(&titles, Some(&User::SingleUser), "/house/index", markdown);
+        assert_eq!(links.get("Safe Combination").cloned(), article("/private/safe"));
+        assert_eq!(links.get("/private/safe").cloned(), article("/private/safe"));
+    }
+
+    #[test]
+    fn table_of_contents() {
+        let policy = AccountPolicyConfig::default();
+        let accounts = accounts(&[]);
+        let context = RenderContext {
+            mentions: Mentions::new(&policy, &accounts),
+            wiki_links: WikiLinks::default(),
+            includes: Includes::default(),
+            sanitizer: None,
+            heading_ids: HashSet::new(),
+        };
+        let rendered = render("[TOC]\n\n# Dishwasher\n## `Filter` Cleaning\n### Notes\n## Notes\n## Parts {#spares}\n", &context);
+
+        let entry = |id: &str, title: &str, level: u8, children: Vec<TocEntry>| TocEntry {
+            id: id.to_owned(),
+            title: title.to_owned(),
+            level,
+            children,
+        };
+        assert_eq!(
+            rendered.toc,
+            vec![entry(
+                "dishwasher",
+                "Dishwasher",
+                1,
+                vec![
+                    entry("filter-cleaning", "Filter Cleaning", 2, vec![entry("notes", "Notes", 3, vec![])]),
+                    entry("notes-1", "Notes", 2, vec![]),
+                    entry("spares", "Parts", 2, vec![]),
+                ]
+            )]
+        );
+        assert!(rendered.toc_inline);
+        assert!(rendered.html.starts_with("<nav class=toc><ul><li><a href=\"#dishwasher\">Dishwasher</a><ul>"));
+        assert!(rendered.html.contains(
+            "<h2 id=\"filter-cleaning\"><code>Filter</code> Cleaning<a class=permalink href=\"#filter-cleaning\" aria-label=\"Permalink\">#</a></h2>"
+        ));
+    }
+}
@@ -0,0 +1,765 @@
+use snafu::Snafu;
+use tera::escape_html;
+
+/// A formula that couldn't be converted to MathML.
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum MathError {
+    #[snafu(display("Unknown command `\\{name}`."))]
+    UnknownCommand { name: String },
+    #[snafu(display("Unknown environment `{name}`."))]
+    UnknownEnvironment { name: String },
+    #[snafu(display("Expected {expected}, found {found}."))]
+    Unexpected { expected: &'static str, found: String },
+    #[snafu(display("`{command}` is missing an argument."))]
+    MissingArgument { command: String },
+    #[snafu(display("Formulas can only be nested {MAX_DEPTH} deep."))]
+    TooDeep,
+}
+
+/// How deeply groups, arguments and environments may be nested in a formula. The parser recurses for
+/// each level, so without a limit a formula like `{{{{...` could overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Converts a TeX formula to MathML, which browsers display without any JavaScript. Only the
+/// commonly used subset of TeX math is supported: scripts, fractions, roots, Greek letters and
+/// symbols, accents, fences with `\left` and `\right`, and matrix-like environments.
+pub fn to_mathml(tex: &str, display: bool) -> Result<String, MathError> {
+    let mut parser = TexParser {
+        tex,
+        position: 0,
+        brackets: 0,
+        depth: 0,
+    };
+    let (row, stop) = parser.parse_row()?;
+    stop.expect(Stop::End, "the end of the formula")?;
+
+    let display = if display { " display=block" } else { "" };
+    Ok(format!(
+        "<math{display}><semantics><mrow>{row}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        escape_html(tex)
+    ))
+}
+
+/// Renders a formula as MathML, or as its TeX source marked as an error if it can't be converted.
+pub fn render(tex: &str, display: bool) -> String {
+    match to_mathml(tex, display) {
+        Ok(mathml) => mathml,
+        Err(err) => format!(
+            "<code class=math-error title=\"{}\">{}</code>",
+            escape_html(&err.to_string()),
+            escape_html(tex)
+        ),
+    }
+}
+
+/// What ended a row of a formula.
+#[derive(Debug, PartialEq, Eq)]
+enum Stop {
+    End,
+    Brace,
+    Bracket,
+    Right,
+    Ampersand,
+    NewRow,
+    EndEnvironment(String),
+}
+
+impl Stop {
+    fn expect(self, expected: Stop, description: &'static str) -> Result<(), MathError> {
+        if self == expected {
+            return Ok(());
+        }
+        Err(MathError::Unexpected {
+            expected: description,
+            found: self.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stop::End => write!(f, "the end of the formula"),
+            Stop::Brace => write!(f, "`}}`"),
+            Stop::Bracket => write!(f, "`]`"),
+            Stop::Right => write!(f, "`\\right`"),
+            Stop::Ampersand => write!(f, "`&`"),
+            Stop::NewRow => write!(f, "`\\\\`"),
+            Stop::EndEnvironment(name) => write!(f, "`\\end{{{name}}}`"),
+        }
+    }
+}
+
+/// A converted piece of a formula, which scripts may be attached to.
+struct Atom {
+    mathml: String,
+    /// Whether scripts go above and below, like the limits of a sum, rather than beside it.
+    limits: bool,
+}
+
+impl From<String> for Atom {
+    fn from(mathml: String) -> Self {
+        Atom { mathml, limits: false }
+    }
+}
+
+struct TexParser<'a> {
+    tex: &'a str,
+    position: usize,
+    /// How many optional arguments in square brackets are being parsed, in which `]` ends a row.
+    brackets: usize,
+    /// How many atoms are being parsed, each containing the next.
+    depth: usize,
+}
+
+impl TexParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.tex[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek()?;
+        self.position += next.len_utf8();
+        Some(next)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    /// Reads the name of a command after its backslash: either a run of letters, or a single other character.
+    fn command_name(&mut self) -> String {
+        let rest = &self.tex[self.position..];
+        let length = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+            Some(length) => length,
+            None => rest.len(),
+        };
+        self.position += length;
+        rest[..length].to_owned()
+    }
+
+    /// Peeks at the name of the command at the current position, if there is one.
+    fn peek_command(&self) -> Option<String> {
+        let mut lookahead = TexParser {
+            tex: self.tex,
+            position: self.position,
+            brackets: 0,
+            depth: 0,
+        };
+        (lookahead.next()? == '\\').then(|| lookahead.command_name())
+    }
+
+    /// Parses atoms until the end of the formula, a closing brace, or a similar marker, which is returned.
+    fn parse_row(&mut self) -> Result<(String, Stop), MathError> {
+        let mut row = String::new();
+        loop {
+            self.skip_whitespace();
+            let stop = match self.peek() {
+                None => Stop::End,
+                Some('}') => Stop::Brace,
+                Some('&') => Stop::Ampersand,
+                Some(']') if self.brackets > 0 => Stop::Bracket,
+                Some('\\') => match self.peek_command().as_deref() {
+                    Some("\\") => Stop::NewRow,
+                    Some("right") => Stop::Right,
+                    Some("end") => {
+                        self.position += "\\end".len();
+                        return Ok((row, Stop::EndEnvironment(self.text_argument("\\end")?)));
+                    }
+                    _ => {
+                        row.push_str(&self.parse_scripted()?);
+                        continue;
+                    }
+                },
+                _ => {
+                    row.push_str(&self.parse_scripted()?);
+                    continue;
+                }
+            };
+            match stop {
+                Stop::End => {}
+                Stop::NewRow => self.position += "\\\\".len(),
+                Stop::Right => self.position += "\\right".len(),
+                _ => {
+                    self.next();
+                }
+            }
+            return Ok((row, stop));
+        }
+    }
+
+    /// Parses an atom along with any superscript and subscript attached to it.
+    fn parse_scripted(&mut self) -> Result<String, MathError> {
+        let base = match self.peek() {
+            Some('^' | '_') => Atom::from("<mrow></mrow>".to_owned()),
+            _ => self.parse_atom(false)?,
+        };
+        let mut superscript = None;
+        let mut subscript = None;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('^') if superscript.is_none() => {
+                    self.next();
+                    superscript = Some(self.parse_argument("^")?);
+                }
+                Some('_') if subscript.is_none() => {
+                    self.next();
+                    subscript = Some(self.parse_argument("_")?);
+                }
+                _ => break,
+            }
+        }
+
+        let (under, over, both) = match base.limits {
+            true => ("munder", "mover", "munderover"),
+            false => ("msub", "msup", "msubsup"),
+        };
+        let base = base.mathml;
+        Ok(match (subscript, superscript) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{under}>{base}{sub}</{under}>"),
+            (None, Some(sup)) => format!("<{over}>{base}{sup}</{over}>"),
+            (Some(sub), Some(sup)) => format!("<{both}>{base}{sub}{sup}</{both}>"),
+        })
+    }
+
+    /// Parses the argument of a command or script, which is a group in braces or a single character or command.
+    fn parse_argument(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None | Some('}' | '&' | '^' | '_') => MissingArgumentSnafu { command }.fail(),
+            _ => Ok(self.parse_atom(true)?.mathml),
+        }
+    }
+
+    /// Reads the contents of a braced argument as plain text, such as the name of an environment.
+    fn text_argument(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_whitespace();
+        if self.next() != Some('{') {
+            return MissingArgumentSnafu { command }.fail();
+        }
+        let start = self.position;
+        let mut depth = 0usize;
+        loop {
+            match self.next() {
+                Some('{') => depth += 1,
+                Some('}') if depth == 0 => return Ok(self.tex[start..self.position - 1].to_owned()),
+                Some('}') => depth -= 1,
+                Some('\\') => {
+                    self.next();
+                }
+                Some(_) => {}
+                None => return MissingArgumentSnafu { command }.fail(),
+            }
+        }
+    }
+
+    /// Parses a group in braces, returning its contents.
+    fn parse_group(&mut self) -> Result<String, MathError> {
+        let (row, stop) = self.parse_row()?;
+        stop.expect(Stop::Brace, "`}`")?;
+        Ok(format!("<mrow>{row}</mrow>"))
+    }
+
+    /// Parses a single atom. As an argument, only one digit of a number is taken, so `\frac12` is ½.
+    /// Every nested part of a formula is parsed within an atom, so this is where nesting is limited.
+    fn parse_atom(&mut self, argument: bool) -> Result<Atom, MathError> {
+        if self.depth == MAX_DEPTH {
+            return TooDeepSnafu.fail();
+        }
+        self.depth += 1;
+        let atom = self.parse_nested_atom(argument);
+        self.depth -= 1;
+        atom
+    }
+
+    fn parse_nested_atom(&mut self, argument: bool) -> Result<Atom, MathError> {
+        let Some(c) = self.next() else {
+            return Ok(Atom::from(String::new()));
+        };
+        let mathml = match c {
+            '0'..='9' | '.' => {
+                let start = self.position - 1;
+                if !argument {
+                    while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                        self.next();
+                    }
+                }
+                format!("<mn>{}</mn>", &self.tex[start..self.position])
+            }
+            '{' => self.parse_group()?,
+            '\\' => return self.parse_command(),
+            '~' => "<mspace width=\"0.25em\"/>".to_owned(),
+            '-' => "<mo>\u{2212}</mo>".to_owned(),
+            '\'' => "<mo>\u{2032}</mo>".to_owned(),
+            c if c.is_alphabetic() => format!("<mi>{c}</mi>"),
+            c => format!("<mo>{}</mo>", escape_html(&c.to_string())),
+        };
+        Ok(Atom::from(mathml))
+    }
+
+    fn parse_command(&mut self) -> Result<Atom, MathError> {
+        let name = self.command_name();
+        if let Some(operator) = large_operator(&name) {
+            return Ok(Atom {
+                mathml: operator,
+                limits: true,
+            });
+        }
+        if let Some(mathml) = symbol(&name) {
+            return Ok(Atom::from(mathml));
+        }
+
+        let command = format!("\\{name}");
+        let mathml = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument(&command)?;
+                let denominator = self.parse_argument(&command)?;
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" => {
+                let n = self.parse_argument(&command)?;
+                let k = self.parse_argument(&command)?;
+                format!("<mrow><mo>(</mo><mfrac linethickness=0>{n}{k}</mfrac><mo>)</mo></mrow>")
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                if self.peek() == Some('[') {
+                    self.next();
+                    self.brackets += 1;
+                    let (index, stop) = self.parse_row()?;
+                    self.brackets -= 1;
+                    stop.expect(Stop::Bracket, "`]`")?;
+                    let radicand = self.parse_argument(&command)?;
+                    format!("<mroot>{radicand}<mrow>{index}</mrow></mroot>")
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_argument(&command)?)
+                }
+            }
+            "text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", escape_html(&self.text_argument(&command)?)),
+            "mathrm" | "operatorname" => {
+                let text = self.text_argument(&command)?;
+                match text.chars().count() {
+                    1 => format!("<mi mathvariant=normal>{}</mi>", escape_html(&text)),
+                    _ => format!("<mi>{}</mi>", escape_html(&text)),
+                }
+            }
+            "mathbf" | "mathbb" => {
+                let text = self.text_argument(&command)?;
+                let styled = text.chars().filter(|c| !c.is_whitespace()).map(|c| styled_letter(&name, c)).collect::<String>();
+                format!("<mi>{}</mi>", escape_html(&styled))
+            }
+            "left" => {
+                let open = self.delimiter(&command)?;
+                let (row, stop) = self.parse_row()?;
+                stop.expect(Stop::Right, "`\\right`")?;
+                let close = self.delimiter("\\right")?;
+                format!("<mrow>{open}{row}{close}</mrow>")
+            }
+            "begin" => self.parse_environment()?,
+            "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            _ => match accent(&name) {
+                Some((mark, over)) => {
+                    let base = self.parse_argument(&command)?;
+                    match over {
+                        true => format!("<mover accent=true>{base}<mo>{mark}</mo></mover>"),
+                        false => format!("<munder accentunder=true>{base}<mo>{mark}</mo></munder>"),
+                    }
+                }
+                None if matches!(name.as_str(), "\\" | "right" | "end") => {
+                    return Err(MathError::Unexpected {
+                        expected: "an argument",
+                        found: format!("`{command}`"),
+                    });
+                }
+                None => return UnknownCommandSnafu { name }.fail(),
+            },
+        };
+        Ok(Atom::from(mathml))
+    }
+
+    /// Reads the delimiter after `\left` or `\right`, where `.` means there isn't one.
+    fn delimiter(&mut self, command: &str) -> Result<String, MathError> {
+        self.skip_whitespace();
+        let delimiter = match self.next() {
+            Some('.') => return Ok(String::new()),
+            Some('\\') => {
+                let name = self.command_name();
+                match name.as_str() {
+                    "{" | "lbrace" => "{",
+                    "}" | "rbrace" => "}",
+                    "|" | "Vert" => "\u{2016}",
+                    "vert" => "|",
+                    "langle" => "\u{27E8}",
+                    "rangle" => "\u{27E9}",
+                    "lfloor" => "\u{230A}",
+                    "rfloor" => "\u{230B}",
+                    "lceil" => "\u{2308}",
+                    "rceil" => "\u{2309}",
+                    _ => return UnknownCommandSnafu { name }.fail(),
+                }
+                .to_owned()
+            }
+            Some(c) if "()[]|/".contains(c) => c.to_string(),
+            _ => return MissingArgumentSnafu { command }.fail(),
+        };
+        Ok(format!("<mo fence=true>{delimiter}</mo>"))
+    }
+
+    /// Parses an environment like `\begin{pmatrix} a & b \\ c & d \end{pmatrix}` as a table.
+    fn parse_environment(&mut self) -> Result<String, MathError> {
+        let name = self.text_argument("\\begin")?;
+        let (open, close, align) = match name.as_str() {
+            "matrix" | "array" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("\u{2016}", "\u{2016}", None),
+            "cases" => ("{", "", Some("left")),
+            "aligned" | "align" | "align*" | "gathered" => ("", "", Some("right left")),
+            _ => return UnknownEnvironmentSnafu { name }.fail(),
+        };
+        if name == "array" {
+            // The column specification, like `{cc}`, only affects alignment.
+            self.text_argument("\\begin{array}")?;
+        }
+
+        let mut rows = Vec::new();
+        let mut cells = Vec::new();
+        loop {
+            let (cell, stop) = self.parse_row()?;
+            cells.push(cell);
+            match stop {
+                Stop::Ampersand => {}
+                Stop::NewRow => rows.push(std::mem::take(&mut cells)),
+                Stop::EndEnvironment(end) if end == name => break,
+                stop => {
+                    return Err(MathError::Unexpected {
+                        expected: "`\\end`",
+                        found: stop.to_string(),
+                    });
+                }
+            }
+        }
+        // A trailing `\\` doesn't start another row.
+        if cells.len() > 1 || cells.first().is_some_and(|cell| !cell.is_empty()) {
+            rows.push(cells);
+        }
+
+        let mut table = match align {
+            Some(align) => format!("<mtable columnalign=\"{align}\">"),
+            None => "<mtable>".to_owned(),
+        };
+        for row in rows {
+            table.push_str("<mtr>");
+            for cell in row {
+                table.push_str(&format!("<mtd>{cell}</mtd>"));
+            }
+            table.push_str("</mtr>");
+        }
+        table.push_str("</mtable>");
+
+        let fence = |delimiter: &str| match delimiter {
+            "" => String::new(),
+            delimiter => format!("<mo fence=true>{delimiter}</mo>"),
+        };
+        Ok(format!("<mrow>{}{table}{}</mrow>", fence(open), fence(close)))
+    }
+}
+
+/// Operators whose scripts are placed above and below them in display math.
+fn large_operator(name: &str) -> Option<String> {
+    let operator = match name {
+        "sum" => "\u{2211}",
+        "prod" => "\u{220F}",
+        "coprod" => "\u{2210}",
+        "bigcup" => "\u{22C3}",
+        "bigcap" => "\u{22C2}",
+        "bigoplus" => "\u{2A01}",
+        "lim" | "max" | "min" | "sup" | "inf" | "limsup" | "liminf" => {
+            return Some(format!("<mo movablelimits=true form=prefix>{name}</mo>"));
+        }
+        _ => return None,
+    };
+    Some(format!("<mo movablelimits=true>{operator}</mo>"))
+}
+
+/// Converts commands that stand for a single letter or symbol.
+fn symbol(name: &str) -> Option<String> {
+    const GREEK: &[(&str, char)] = &[
+        ("alpha", 'α'),
+        ("beta", 'β'),
+        ("gamma", 'γ'),
+        ("delta", 'δ'),
+        ("epsilon", 'ϵ'),
+        ("varepsilon", 'ε'),
+        ("zeta", 'ζ'),
+        ("eta", 'η'),
+        ("theta", 'θ'),
+        ("vartheta", 'ϑ'),
+        ("iota", 'ι'),
+        ("kappa", 'κ'),
+        ("lambda", 'λ'),
+        ("mu", 'μ'),
+        ("nu", 'ν'),
+        ("xi", 'ξ'),
+        ("pi", 'π'),
+        ("varpi", 'ϖ'),
+        ("rho", 'ρ'),
+        ("varrho", 'ϱ'),
+        ("sigma", 'σ'),
+        ("varsigma", 'ς'),
+        ("tau", 'τ'),
+        ("upsilon", 'υ'),
+        ("phi", 'ϕ'),
+        ("varphi", 'φ'),
+        ("chi", 'χ'),
+        ("psi", 'ψ'),
+        ("omega", 'ω'),
+        ("Gamma", 'Γ'),
+        ("Delta", 'Δ'),
+        ("Theta", 'Θ'),
+        ("Lambda", 'Λ'),
+        ("Xi", 'Ξ'),
+        ("Pi", 'Π'),
+        ("Sigma", 'Σ'),
+        ("Upsilon", 'Υ'),
+        ("Phi", 'Φ'),
+        ("Psi", 'Ψ'),
+        ("Omega", 'Ω'),
+    ];
+    const OPERATORS: &[(&str, &str)] = &[
+        ("times", "×"),
+        ("cdot", "⋅"),
+        ("div", "÷"),
+        ("pm", "±"),
+        ("mp", "∓"),
+        ("ast", "∗"),
+        ("star", "⋆"),
+        ("circ", "∘"),
+        ("bullet", "∙"),
+        ("leq", "≤"),
+        ("le", "≤"),
+        ("geq", "≥"),
+        ("ge", "≥"),
+        ("neq", "≠"),
+        ("ne", "≠"),
+        ("approx", "≈"),
+        ("equiv", "≡"),
+        ("sim", "∼"),
+        ("simeq", "≃"),
+        ("cong", "≅"),
+        ("propto", "∝"),
+        ("ll", "≪"),
+        ("gg", "≫"),
+        ("to", "→"),
+        ("rightarrow", "→"),
+        ("leftarrow", "←"),
+        ("gets", "←"),
+        ("leftrightarrow", "↔"),
+        ("Rightarrow", "⇒"),
+        ("implies", "⇒"),
+        ("Leftarrow", "⇐"),
+        ("Leftrightarrow", "⇔"),
+        ("iff", "⇔"),
+        ("mapsto", "↦"),
+        ("in", "∈"),
+        ("notin", "∉"),
+        ("ni", "∋"),
+        ("subset", "⊂"),
+        ("subseteq", "⊆"),
+        ("supset", "⊃"),
+        ("supseteq", "⊇"),
+        ("cup", "∪"),
+        ("cap", "∩"),
+        ("setminus", "∖"),
+        ("forall", "∀"),
+        ("exists", "∃"),
+        ("neg", "¬"),
+        ("lnot", "¬"),
+        ("land", "∧"),
+        ("wedge", "∧"),
+        ("lor", "∨"),
+        ("vee", "∨"),
+        ("oplus", "⊕"),
+        ("otimes", "⊗"),
+        ("perp", "⊥"),
+        ("parallel", "∥"),
+        ("mid", "∣"),
+        ("angle", "∠"),
+        ("triangle", "△"),
+        ("int", "∫"),
+        ("iint", "∬"),
+        ("iiint", "∭"),
+        ("oint", "∮"),
+        ("ldots", "…"),
+        ("dots", "…"),
+        ("cdots", "⋯"),
+        ("vdots", "⋮"),
+        ("ddots", "⋱"),
+        ("prime", "′"),
+        ("degree", "°"),
+        ("langle", "⟨"),
+        ("rangle", "⟩"),
+        ("lfloor", "⌊"),
+        ("rfloor", "⌋"),
+        ("lceil", "⌈"),
+        ("rceil", "⌉"),
+        ("{", "{"),
+        ("}", "}"),
+        ("|", "‖"),
+        ("%", "%"),
+        ("$", "$"),
+        ("#", "#"),
+        ("&", "&amp;"),
+        ("_", "_"),
+    ];
+    const IDENTIFIERS: &[(&str, &str)] = &[
+        ("infty", "∞"),
+        ("partial", "∂"),
+        ("nabla", "∇"),
+        ("emptyset", "∅"),
+        ("varnothing", "∅"),
+        ("hbar", "ℏ"),
+        ("ell", "ℓ"),
+        ("aleph", "ℵ"),
+    ];
+    const FUNCTIONS: &[&str] = &[
+        "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh", "log", "ln",
+        "lg", "exp", "det", "dim", "gcd", "deg", "arg", "ker", "Pr",
+    ];
+    const SPACES: &[(&str, &str)] = &[
+        (",", "0.1667em"),
+        (":", "0.2222em"),
+        (">", "0.2222em"),
+        (";", "0.2778em"),
+        (" ", "0.25em"),
+        ("quad", "1em"),
+        ("qquad", "2em"),
+    ];
+
+    if let Some((_, letter)) = GREEK.iter().find(|(command, _)| *command == name) {
+        return Some(match letter.is_uppercase() {
+            true => format!("<mi mathvariant=normal>{letter}</mi>"),
+            false => format!("<mi>{letter}</mi>"),
+        });
+    }
+    if let Some((_, operator)) = OPERATORS.iter().find(|(command, _)| *command == name) {
+        return Some(format!("<mo>{operator}</mo>"));
+    }
+    if let Some((_, identifier)) = IDENTIFIERS.iter().find(|(command, _)| *command == name) {
+        return Some(format!("<mi>{identifier}</mi>"));
+    }
+    if FUNCTIONS.contains(&name) {
+        return Some(format!("<mi>{name}</mi><mo>\u{2061}</mo>"));
+    }
+    if let Some((_, width)) = SPACES.iter().find(|(command, _)| *command == name) {
+        return Some(format!("<mspace width=\"{width}\"/>"));
+    }
+    // Negative space can't be represented, so it's dropped.
+    (name == "!").then(String::new)
+}
+
+/// The mark placed above or below a base by an accent command, and whether it goes above.
+fn accent(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "hat" | "widehat" => ("^", true),
+        "bar" | "overline" => ("\u{203E}", true),
+        "vec" | "overrightarrow" => ("\u{2192}", true),
+        "dot" => ("\u{02D9}", true),
+        "ddot" => ("\u{00A8}", true),
+        "tilde" | "widetilde" => ("~", true),
+        "underline" => ("_", false),
+        _ => return None,
+    })
+}
+
+/// Converts a letter or digit to its bold or double-struck form, like `ℝ` for `\mathbb{R}`.
+fn styled_letter(style: &str, c: char) -> char {
+    let (upper, lower, digit) = match style {
+        "mathbb" => {
+            let letterlike = match c {
+                'C' => Some('ℂ'),
+                'H' => Some('ℍ'),
+                'N' => Some('ℕ'),
+                'P' => Some('ℙ'),
+                'Q' => Some('ℚ'),
+                'R' => Some('ℝ'),
+                'Z' => Some('ℤ'),
+                _ => None,
+            };
+            if let Some(letterlike) = letterlike {
+                return letterlike;
+            }
+            (0x1D538, 0x1D552, 0x1D7D8)
+        }
+        _ => (0x1D400, 0x1D41A, 0x1D7CE),
+    };
+    let styled = match c {
+        'A'..='Z' => upper + (c as u32 - 'A' as u32),
+        'a'..='z' => lower + (c as u32 - 'a' as u32),
+        '0'..='9' => digit + (c as u32 - '0' as u32),
+        _ => return c,
+    };
+    char::from_u32(styled).unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::*;
+
+    fn mathml(tex: &str) -> String {
+        let mathml = to_mathml(tex, false).unwrap();
+        let start = "<math><semantics><mrow>".len();
+        let end = mathml.find("<annotation").unwrap() - "</mrow>".len();
+        mathml[start..end].to_owned()
+    }
+
+    #[test]
+    fn convert_tex() {
+        assert_eq!(mathml("x^2 + 10"), "<msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>10</mn>");
+        assert_eq!(mathml("a_{n-1}"), "<msub><mi>a</mi><mrow><mi>n</mi><mo>\u{2212}</mo><mn>1</mn></mrow></msub>");
+        assert_eq!(mathml("\\frac12"), "<mfrac><mn>1</mn><mn>2</mn></mfrac>");
+        assert_eq!(mathml("\\sqrt[3]{8}"), "<mroot><mrow><mn>8</mn></mrow><mrow><mn>3</mn></mrow></mroot>");
+        assert_eq!(mathml("\\alpha \\leq \\Omega"), "<mi>α</mi><mo>≤</mo><mi mathvariant=normal>Ω</mi>");
+        assert_eq!(
+            mathml("\\sum_{i=1}^n i"),
+            "<munderover><mo movablelimits=true>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover><mi>i</mi>"
+        );
+        assert_eq!(mathml("\\left( x \\right."), "<mrow><mo fence=true>(</mo><mi>x</mi></mrow>");
+        assert_eq!(
+            mathml("\\begin{pmatrix} 1 & 0 \\\\ 0 & 1 \\end{pmatrix}"),
+            "<mrow><mo fence=true>(</mo><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr>\
+             <mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr></mtable><mo fence=true>)</mo></mrow>"
+        );
+        assert_eq!(mathml("x \\in \\mathbb{R}"), "<mi>x</mi><mo>∈</mo><mi>ℝ</mi>");
+        assert_eq!(mathml("\\text{if } a<b"), "<mtext>if </mtext><mi>a</mi><mo>&lt;</mo><mi>b</mi>");
+        assert!(to_mathml("x", true).unwrap().starts_with("<math display=block>"));
+
+        assert_eq!(to_mathml("\\frob", false), Err(MathError::UnknownCommand { name: "frob".to_owned() }));
+        assert_eq!(to_mathml("\\frac{1}", false), Err(MathError::MissingArgument { command: "\\frac".to_owned() }));
+        assert_eq!(
+            to_mathml("{x", false),
+            Err(MathError::Unexpected {
+                expected: "`}`",
+                found: "the end of the formula".to_owned()
+            })
+        );
+        assert_eq!(mathml("[0, 1]"), "<mo>[</mo><mn>0</mn><mo>,</mo><mn>1</mn><mo>]</mo>");
+        assert_eq!(
+            render("\\frob <b>", false),
+            "<code class=math-error title=\"Unknown command `\\frob`.\">\\frob &lt;b&gt;</code>"
+        );
+
+        // Deeply nested formulas are rejected rather than overflowing the stack.
+        assert_eq!(to_mathml(&"{".repeat(100_000), false), Err(MathError::TooDeep));
+        assert_eq!(to_mathml(&"\\sqrt".repeat(100_000), false), Err(MathError::TooDeep));
+        assert_eq!(to_mathml(&"x^{".repeat(100_000), false), Err(MathError::TooDeep));
+        assert!(to_mathml(&format!("{}x{}", "{".repeat(60), "}".repeat(60)), false).is_ok());
+    }
+}
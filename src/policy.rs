@@ -0,0 +1,245 @@
+use crate::auth::Username;
+use crate::config::{AccountConfig, AccountPolicyConfig};
+use snafu::Snafu;
+
+/// Common passwords that are rejected regardless of their estimated strength. Compared
+/// case-insensitively, and also with any trailing digits and symbols removed.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "123456789", "12345678", "1234567890", "password", "qwerty", "qwertyuiop", "qwerty123", "1q2w3e4r",
+    "1qaz2wsx", "zaq12wsx", "asdfghjkl", "asdfgh", "zxcvbnm", "abc123", "abcdef", "iloveyou", "letmein", "welcome",
+    "monkey", "dragon", "football", "baseball", "basketball", "soccer", "hockey", "master", "shadow", "sunshine",
+    "princess", "superman", "batman", "trustno1", "starwars", "whatever", "freedom", "michael", "jennifer", "jordan",
+    "hunter", "killer", "charlie", "thomas", "ashley", "daniel", "jessica", "pepper", "ginger", "cookie", "cheese",
+    "summer", "winter", "spring", "autumn", "flower", "hello", "secret", "access", "admin", "administrator", "root",
+    "login", "passw0rd", "p@ssw0rd", "p@ssword", "changeme", "default", "guest", "test", "testing", "qazwsx",
+    "mustang", "harley", "ranger", "buster", "tigger", "robert", "matthew", "andrew", "joshua", "computer",
+    "internet", "google", "samsung", "apple", "pokemon", "minecraft", "liverpool", "chelsea", "arsenal", "blink182",
+    "naruto", "lovely", "loveme", "babygirl", "anthony", "nicole", "hannah", "maggie", "purple", "orange", "banana",
+    "chocolate", "smolwik", "wiki",
+];
+
+/// A violation of the account policy, with a message suitable for showing the user.
+#[derive(Debug, Snafu)]
+pub enum PolicyViolation {
+    #[snafu(display("Usernames can't be empty."))]
+    EmptyUsername,
+    #[snafu(display("Usernames can't be longer than {max} characters."))]
+    UsernameTooLong { max: usize },
+    #[snafu(display("Usernames may only contain letters, digits and these characters: {allowed}"))]
+    InvalidUsernameCharacter { allowed: String },
+    #[snafu(display("An account with the username `{existing}` already exists."))]
+    UsernameTaken { existing: Username },
+    #[snafu(display("Passwords must be at least {min} characters long."))]
+    PasswordTooShort { min: usize },
+    #[snafu(display("This password is too common. Please choose a different one."))]
+    CommonPassword,
+    #[snafu(display("Passwords can't contain the username."))]
+    PasswordContainsUsername,
+    #[snafu(display("This password is too easy to guess. Try making it longer, or avoid repeated and sequential characters."))]
+    WeakPassword,
+}
+
+/// Validates a new username against the policy, including that no other account already has the
+/// username. `previous` is the account's current username when renaming, which is ignored when
+/// checking for existing accounts.
+pub fn validate_username(
+    policy: &AccountPolicyConfig,
+    account_config: &AccountConfig,
+    username: &Username,
+    previous: Option<&Username>,
+) -> Result<(), PolicyViolation> {
+    let name = username.as_str();
+    if name.is_empty() {
+        return Err(PolicyViolation::EmptyUsername);
+    }
+    if name.chars().count() > policy.max_username_length {
+        return Err(PolicyViolation::UsernameTooLong {
+            max: policy.max_username_length,
+        });
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || policy.username_characters.contains(c)) {
+        return Err(PolicyViolation::InvalidUsernameCharacter {
+            allowed: policy.username_characters.clone(),
+        });
+    }
+
+    // A renamed account may change the case of its own username.
+    let existing = account_config
+        .accounts
+        .iter()
+        .map(|acc| &acc.username)
+        .filter(|existing| Some(*existing) != previous)
+        .find(|existing| is_same_username(policy, existing, username));
+    match existing {
+        Some(existing) => Err(PolicyViolation::UsernameTaken {
+            existing: existing.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Checks if two usernames belong to the same account, ignoring case if the policy does.
+pub fn is_same_username(policy: &AccountPolicyConfig, first: &Username, second: &Username) -> bool {
+    match policy.case_insensitive_usernames {
+        true => first.as_str().eq_ignore_ascii_case(second.as_str()),
+        false => first == second,
+    }
+}
+
+/// Finds two accounts with usernames differing only in case, which can't be told apart when logging
+/// in if the policy ignores case.
+pub fn find_case_conflict<'a>(
+    policy: &AccountPolicyConfig,
+    account_config: &'a AccountConfig,
+) -> Option<(&'a Username, &'a Username)> {
+    if !policy.case_insensitive_usernames {
+        return None;
+    }
+    let usernames = account_config.accounts.iter().map(|acc| &acc.username).collect::<Vec<_>>();
+    usernames.iter().enumerate().find_map(|(i, first)| {
+        usernames[i + 1..]
+            .iter()
+            .find(|second| is_same_username(policy, first, second))
+            .map(|second| (*first, *second))
+    })
+}
+
+/// Validates a new password against the policy. The username of the account, if any, is used to
+/// reject passwords based on it.
+pub fn validate_password(
+    policy: &AccountPolicyConfig,
+    password: &str,
+    username: Option<&Username>,
+) -> Result<(), PolicyViolation> {
+    if password.chars().count() < policy.min_password_length {
+        return Err(PolicyViolation::PasswordTooShort {
+            min: policy.min_password_length,
+        });
+    }
+
+    let lowercase = password.to_lowercase();
+    if policy.deny_common_passwords && is_common(&lowercase) {
+        return Err(PolicyViolation::CommonPassword);
+    }
+    if let Some(username) = username
+        && username.as_str().chars().count() >= 3
+        && lowercase.contains(&username.as_str().to_lowercase())
+    {
+        return Err(PolicyViolation::PasswordContainsUsername);
+    }
+    if estimate_strength(password) < policy.min_password_strength {
+        return Err(PolicyViolation::WeakPassword);
+    }
+    Ok(())
+}
+
+fn is_common(lowercase: &str) -> bool {
+    let stripped = lowercase.trim_end_matches(|c: char| !c.is_alphabetic());
+    COMMON_PASSWORDS.contains(&lowercase) || COMMON_PASSWORDS.contains(&stripped)
+}
+
+/// Estimates the strength of a password on a scale from 0 (trivially guessable) to 4 (very hard to
+/// guess). Characters continuing a run of repeated or sequential characters, like `aaaa` or `1234`,
+/// add almost nothing to the strength, since guessing tools try them early.
+pub fn estimate_strength(password: &str) -> u8 {
+    let chars = password.chars().collect::<Vec<_>>();
+    let mut pool = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+
+    let mut effective_length = 0.0;
+    for (i, c) in chars.iter().enumerate() {
+        let continues_run = i > 0 && {
+            let step = *c as i64 - chars[i - 1] as i64;
+            step.abs() <= 1
+        };
+        effective_length += if continues_run { 0.25 } else { 1.0 };
+    }
+
+    let guesses_log10 = effective_length * f64::log10(pool.max(1) as f64);
+    match guesses_log10 {
+        g if g < 4.0 => 0,
+        g if g < 7.0 => 1,
+        g if g < 10.0 => 2,
+        g if g < 13.0 => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Account;
+    use crate::config::{AccountConfig, AccountPolicyConfig, PasswordHashingConfig};
+    use crate::policy::*;
+    use std::assert_matches;
+
+    fn account_config() -> AccountConfig {
+        AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: vec![Account::new("Alex".into(), "unused password", &PasswordHashingConfig::default())],
+            used_invites: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn username_policy() {
+        let policy = AccountPolicyConfig::default();
+        let config = account_config();
+        let validate = |username: &str| validate_username(&policy, &config, &username.into(), None);
+
+        assert_matches!(validate("morgan.lee-2"), Ok(()));
+        assert_matches!(validate(""), Err(PolicyViolation::EmptyUsername));
+        assert_matches!(validate("morgan lee"), Err(PolicyViolation::InvalidUsernameCharacter { .. }));
+        assert_matches!(validate("morgan/lee"), Err(PolicyViolation::InvalidUsernameCharacter { .. }));
+        assert_matches!(validate(" morgan"), Err(PolicyViolation::InvalidUsernameCharacter { .. }));
+        assert_matches!(validate(&"a".repeat(33)), Err(PolicyViolation::UsernameTooLong { .. }));
+        assert_matches!(validate("alex"), Err(PolicyViolation::UsernameTaken { .. }));
+
+        // Renaming an account to a different case of its own name is allowed.
+        assert_matches!(validate_username(&policy, &config, &"alex".into(), Some(&"Alex".into())), Ok(()));
+    }
+
+    #[test]
+    fn case_conflicts() {
+        let mut policy = AccountPolicyConfig::default();
+        let mut config = account_config();
+        assert_eq!(find_case_conflict(&policy, &config), None);
+
+        config.accounts.push(Account::new("ALEX".into(), "unused password", &PasswordHashingConfig::default()));
+        let (first, second) = find_case_conflict(&policy, &config).unwrap();
+        assert_eq!((first.as_str(), second.as_str()), ("Alex", "ALEX"));
+
+        policy.case_insensitive_usernames = false;
+        assert_eq!(find_case_conflict(&policy, &config), None);
+        assert!(!is_same_username(&policy, &"alex".into(), &"Alex".into()));
+    }
+
+    #[test]
+    fn password_policy() {
+        let policy = AccountPolicyConfig::default();
+        let validate = |password: &str| validate_password(&policy, password, Some(&"morgan".into()));
+
+        assert_matches!(validate("correct horse battery staple"), Ok(()));
+        assert_matches!(validate("Tr0ub4dor&3"), Ok(()));
+        assert_matches!(validate(""), Err(PolicyViolation::PasswordTooShort { .. }));
+        assert_matches!(validate("Password123!"), Err(PolicyViolation::CommonPassword));
+        assert_matches!(validate("morgan's password"), Err(PolicyViolation::PasswordContainsUsername));
+        assert_matches!(validate("aaaaaaaaaaaa"), Err(PolicyViolation::WeakPassword));
+        assert_matches!(validate("abcdefghijkl"), Err(PolicyViolation::WeakPassword));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::article::{ArticleWriteError, RawArticle};
+use crate::auth::Username;
+use crate::config::Config;
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+/// An article with an access list that contains a username.
+#[derive(Serialize, Debug, Clone)]
+pub struct ArticleReference {
+    /// The URL path of the article.
+    pub url_path: String,
+    /// The title of the article.
+    pub title: String,
+    /// Whether the username is in the list of users allowed to view the article.
+    pub view_access: bool,
+    /// Whether the username is in the list of users allowed to edit the article.
+    pub edit_access: bool,
+}
+
+#[derive(Debug, Snafu)]
+pub enum ReferenceError {
+    #[snafu(display("Failed to open the directory {}: {}", path.display(), source))]
+    DirectoryOpen { source: std::io::Error, path: PathBuf },
+    #[snafu(display("Failed to open an entry in {}: {}", path.display(), source))]
+    EntryOpen { source: std::io::Error, path: PathBuf },
+    #[snafu(display("Failed to update the access lists of {}: {}", path, source))]
+    ArticleWrite { source: ArticleWriteError, path: String },
+}
+
+/// Gets the names of the settings in `config.toml` with access lists that contain the username.
+/// The configuration is only read at startup, so these must be updated by hand.
+pub fn find_config_references(config: &Config, username: &Username) -> Vec<&'static str> {
+    [
+        ("create_access", &config.create_access),
+        ("administrator_access", &config.administrator_access),
+        ("discovery_access", &config.discovery_access),
+    ]
+    .into_iter()
+    .filter(|(_, access)| access.references(username))
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// Finds every article with an access list that contains the username.
+pub async fn find_article_references(
+    article_root: &Path,
+    username: &Username,
+) -> Result<Vec<ArticleReference>, ReferenceError> {
+    let mut references = Vec::new();
+    for (filepath, url_path) in find_articles(article_root).await? {
+        let Some(article) = read_article(&filepath, &url_path).await else {
+            continue;
+        };
+
+        let view_access = article.metadata.view_access.references(username);
+        let edit_access = article.metadata.edit_access.references(username);
+        if view_access || edit_access {
+            references.push(ArticleReference {
+                url_path,
+                title: article.metadata.title,
+                view_access,
+                edit_access,
+            });
+        }
+    }
+    references.sort_by(|first, second| first.url_path.cmp(&second.url_path));
+    Ok(references)
+}
+
+/// Replaces the username in the access lists of every article, or removes it if there's no
+/// replacement. Returns the URL paths and new contents of the articles that were changed.
+pub async fn rewrite_article_references(
+    article_root: &Path,
+    old: &Username,
+    new: Option<&Username>,
+) -> Result<Vec<(String, RawArticle)>, ReferenceError> {
+    let mut rewritten = Vec::new();
+    for (filepath, url_path) in find_articles(article_root).await? {
+        let Some(mut article) = read_article(&filepath, &url_path).await else {
+            continue;
+        };
+
+        // Both must be replaced, so avoid short-circuiting.
+        let view_changed = article.metadata.view_access.replace_username(old, new);
+        let edit_changed = article.metadata.edit_access.replace_username(old, new);
+        if view_changed || edit_changed {
+            article
+                .write_to_path(&filepath, &url_path)
+                .await
+                .with_context(|_| ArticleWriteSnafu { path: url_path.clone() })?;
+            rewritten.push((url_path, article));
+        }
+    }
+    Ok(rewritten)
+}
+
+async fn read_article(filepath: &Path, url_path: &str) -> Option<RawArticle> {
+    // A single broken article shouldn't prevent managing accounts, so it's skipped.
+    match RawArticle::read_from_path(filepath, url_path).await {
+        Ok(article) => Some(article),
+        Err(err) => {
+            eprintln!("WARN: Skipped article while searching for access references: {err}");
+            None
+        }
+    }
+}
+
+/// Finds the file and URL paths of every article under the article root.
+pub async fn find_articles(article_root: &Path) -> Result<Vec<(PathBuf, String)>, ReferenceError> {
+    let mut articles = Vec::new();
+    let mut directories = vec![article_root.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        let mut entries = tokio::fs::read_dir(&directory)
+            .await
+            .with_context(|_| DirectoryOpenSnafu { path: &directory })?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .with_context(|_| EntryOpenSnafu { path: &directory })?
+        {
+            let filepath = entry.path();
+            // Symlinks are followed, as they are when the article is read. Broken ones are skipped.
+            let Ok(metadata) = tokio::fs::metadata(&filepath).await else {
+                continue;
+            };
+            if metadata.is_dir() {
+                directories.push(filepath);
+            } else if metadata.is_file() && filepath.extension().is_some_and(|ext| ext == "md") {
+                let relative = filepath
+                    .with_extension("")
+                    .strip_prefix(article_root)
+                    .expect("All paths should be descendents of the article root.")
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                articles.push((filepath, format!("/{relative}")));
+            }
+        }
+    }
+    Ok(articles)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::article::RawArticle;
+    use crate::auth::Access;
+    use crate::metadata::Metadata;
+    use crate::references::*;
+    use testdir::testdir;
+
+    #[tokio::test]
+    async fn rewrite_renamed_username() {
+        let root = testdir!();
+        std::fs::create_dir(root.join("notes")).unwrap();
+        let article = |view: Vec<Username>| RawArticle {
+            metadata: Metadata {
+                title: "Article".to_owned(),
+                edit_access: Access::Authenticated,
+                view_access: Access::Accounts(view),
+            },
+            markdown: String::new(),
+        };
+        article(vec!["alex".into(), "morgan".into()])
+            .write_to_path(&root.join("notes/shared.md"), "/notes/shared")
+            .await
+            .unwrap();
+        article(vec!["morgan".into()]).write_to_path(&root.join("private.md"), "/private").await.unwrap();
+
+        let references = find_article_references(&root, &"alex".into()).await.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].url_path, "/notes/shared");
+        assert!(references[0].view_access);
+        assert!(!references[0].edit_access);
+
+        let rewritten = rewrite_article_references(&root, &"alex".into(), Some(&"sam".into())).await.unwrap();
+        assert_eq!(rewritten.len(), 1);
+        assert_eq!(rewritten[0].0, "/notes/shared");
+        assert!(rewritten[0].1.metadata.view_access.references(&"sam".into()));
+        assert!(find_article_references(&root, &"alex".into()).await.unwrap().is_empty());
+        assert_eq!(find_article_references(&root, &"sam".into()).await.unwrap().len(), 1);
+        assert_eq!(find_article_references(&root, &"morgan".into()).await.unwrap().len(), 2);
+    }
+}
@@ -28,16 +28,17 @@ impl Renderer {
         self.tera.render(template, &self.build_context(session, title))
     }
 
-    pub fn render_template_with_context(
-        &self,
-        session: &Session,
-        template: &str,
-        title: &str,
-        context: Context,
-    ) -> Result<String, tera::Error> {
+    /// Builds the full context of a page, adding the details of the session to the page's own context.
+    /// Templates are only loaded at startup, so this determines everything the page is rendered from.
+    pub fn page_context(&self, session: &Session, title: &str, context: Context) -> Context {
         let mut ctx = self.build_context(session, title);
         ctx.extend(context);
-        self.tera.render(template, &ctx)
+        ctx
+    }
+
+    /// Renders the template with a context built by [Self::page_context].
+    pub fn render_page(&self, template: &str, context: &Context) -> Result<String, tera::Error> {
+        self.tera.render(template, context)
     }
 
     /// Renders the error template with the provided title and error details. If the error template
@@ -1,35 +1,57 @@
 use crate::article::{ArticleReadError, ArticleWriteError};
 use crate::config::ConfigReadError;
 use crate::filesystem::FileWriteError;
+use crate::references::ReferenceError;
+use crate::signed::SignedTokenError;
 use crate::routes::discovery::DiscoveryTreeError;
+use axum::extract::multipart::{MultipartError, MultipartRejection};
 use axum::extract::rejection::FormRejection;
 use axum::http::StatusCode;
 use axum_core::body::Body;
 use axum_core::response::{IntoResponse, Response};
 use std::error::Error;
+use std::time::SystemTime;
 use tera::Context;
 
 pub struct TemplatedResponse {
     pub template: &'static str,
     pub context: Context,
+    /// When the content of the page last changed, if known. Enables conditional requests for the page.
+    pub last_modified: Option<SystemTime>,
 }
 
 impl TemplatedResponse {
     pub fn new(template: &'static str, context: Context) -> TemplatedResponse {
-        TemplatedResponse { template, context }
+        TemplatedResponse {
+            template,
+            context,
+            last_modified: None,
+        }
     }
 }
 
+/// Marks a response to be answered with `304 Not Modified` when the client already has the page.
+#[derive(Clone, Copy, Debug)]
+pub struct LastModified(pub SystemTime);
+
 impl IntoResponse for TemplatedResponse {
     fn into_response(self) -> Response {
         let mut response = Response::new(Body::empty());
         let extensions = response.extensions_mut();
         extensions.insert(self.template);
         extensions.insert(self.context);
+        if let Some(last_modified) = self.last_modified {
+            extensions.insert(LastModified(last_modified));
+        }
         response
     }
 }
 
+/// Marks an error response asking the user to authenticate, as opposed to one rejecting the
+/// credentials they provided.
+#[derive(Clone, Copy, Debug)]
+pub struct AuthenticationRequired;
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct ErrorResponse {
     pub status_code: StatusCode,
@@ -62,6 +84,14 @@ impl ErrorResponse {
         }
     }
 
+    pub fn basic_auth_unavailable() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::UNAUTHORIZED,
+            title: "Basic authentication unavailable".to_owned(),
+            details: "Two-factor authentication is enabled for this user, so Basic authentication can't be used. Use an API token instead.".to_owned(),
+        }
+    }
+
     pub fn invalid_credentials() -> Self {
         ErrorResponse {
             status_code: StatusCode::UNAUTHORIZED,
@@ -70,6 +100,51 @@ impl ErrorResponse {
         }
     }
 
+    pub fn invalid_token() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::UNAUTHORIZED,
+            title: "Invalid token".to_owned(),
+            details: "The API token provided is invalid or has been revoked.".to_owned(),
+        }
+    }
+
+    pub fn invalid_totp_code() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::UNAUTHORIZED,
+            title: "Invalid code".to_owned(),
+            details: "The two-factor authentication code provided is invalid or has expired.".to_owned(),
+        }
+    }
+
+    pub fn totp_already_enabled() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::CONFLICT,
+            title: "Two-factor already enabled".to_owned(),
+            details: "Two-factor authentication is already enabled. Disable it with a code from your current \
+                authenticator before enrolling a new one."
+                .to_owned(),
+        }
+    }
+
+    pub fn login_expired() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::UNAUTHORIZED,
+            title: "Login expired".to_owned(),
+            details: "The login has expired or was never started, please log in again.".to_owned(),
+        }
+    }
+
+    pub fn too_many_login_attempts(retry_after: std::time::Duration) -> Self {
+        ErrorResponse {
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+            title: "Too many login attempts".to_owned(),
+            details: format!(
+                "Too many failed login attempts have been made. Please try again in {} seconds.",
+                retry_after.as_secs().max(1)
+            ),
+        }
+    }
+
     pub fn already_authenticated() -> Self {
         ErrorResponse {
             status_code: StatusCode::BAD_REQUEST,
@@ -86,6 +161,40 @@ impl ErrorResponse {
         }
     }
 
+    pub fn token_not_allowed() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::FORBIDDEN,
+            title: "Access forbidden".to_owned(),
+            details: "API tokens can only be used for articles. Log in to use this page.".to_owned(),
+        }
+    }
+
+    pub fn tokens_need_account() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::FORBIDDEN,
+            title: "Access forbidden".to_owned(),
+            details: "API tokens are kept with accounts in `accounts.toml`, and there's no account with your username. \
+                Ask an administrator to add one."
+                .to_owned(),
+        }
+    }
+
+    pub fn cross_site_request() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::FORBIDDEN,
+            title: "Cross-site request blocked".to_owned(),
+            details: "The request was sent from another site, so it was blocked to protect your account.".to_owned(),
+        }
+    }
+
+    pub fn invite_used() -> Self {
+        ErrorResponse {
+            status_code: StatusCode::BAD_REQUEST,
+            title: "Bad request".to_owned(),
+            details: "This invite has already been used. Log in with your username and password instead.".to_owned(),
+        }
+    }
+
     pub fn account_not_found(username: &crate::auth::Username) -> Self {
         ErrorResponse {
             status_code: StatusCode::NOT_FOUND,
@@ -189,6 +298,25 @@ impl From<DiscoveryTreeError> for ErrorResponse {
     }
 }
 
+impl From<ReferenceError> for ErrorResponse {
+    fn from(value: ReferenceError) -> Self {
+        match value {
+            ReferenceError::ArticleWrite { source, path: _ } => Self::from(source),
+            _ => Self::internal_error(value.to_string()),
+        }
+    }
+}
+
+impl From<SignedTokenError> for ErrorResponse {
+    fn from(value: SignedTokenError) -> Self {
+        ErrorResponse {
+            status_code: StatusCode::BAD_REQUEST,
+            title: "Invalid link".to_owned(),
+            details: format!("{value} Ask an administrator for a new link."),
+        }
+    }
+}
+
 impl From<ConfigReadError> for ErrorResponse {
     fn from(value: ConfigReadError) -> Self {
         Self::internal_error(value.to_string())
@@ -239,3 +367,15 @@ impl From<FormRejection> for ErrorResponse {
         }
     }
 }
+
+impl From<MultipartRejection> for ErrorResponse {
+    fn from(value: MultipartRejection) -> Self {
+        Self::bad_request_with_details(value.body_text())
+    }
+}
+
+impl From<MultipartError> for ErrorResponse {
+    fn from(value: MultipartError) -> Self {
+        Self::bad_request_with_details(value.body_text())
+    }
+}
@@ -0,0 +1,463 @@
+use crate::audit::{AuditAction, Auditor};
+use crate::auth::*;
+use crate::authentication::verify_credentials;
+use crate::config::{AccountConfig, AccountPolicyConfig, PasswordHashingConfig};
+use crate::extractors::Form;
+use crate::policy::{self, PolicyViolation};
+use crate::responses::TemplatedResponse;
+use crate::throttle::{LoginAttempt, LoginTarget};
+use crate::totp;
+use crate::*;
+use axum::extract::State;
+use axum::routing::post;
+use snafu::{ResultExt, Snafu, ensure};
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/special:account", get(account_get_handler))
+        .route("/special:account:change_password", post(change_password_handler))
+        .route("/special:account:totp", get(totp_get_handler).post(totp_enable_handler))
+        .route("/special:account:totp:disable", post(totp_disable_handler))
+        .route("/special:account:tokens", get(tokens_get_handler).post(create_token_handler))
+        .route("/special:account:tokens:revoke", post(revoke_token_handler))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordForm {
+    /// The user's current password, proving that the session isn't being used by someone else.
+    pub current_password: String,
+    /// The new password.
+    pub new_password: String,
+    /// The new password again, to catch typos.
+    pub confirm_password: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for ChangePasswordForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct EnableTotpForm {
+    /// The base32 encoded secret shown to the user during enrollment.
+    pub secret: String,
+    /// The current code generated from the secret, proving that it was enrolled successfully.
+    pub code: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for EnableTotpForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct DisableTotpForm {
+    /// The current code, proving that the user still holds the enrolled secret.
+    pub code: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for DisableTotpForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateTokenForm {
+    /// The name of the new token, used to tell tokens apart.
+    pub name: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for CreateTokenForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeTokenForm {
+    /// The name of the token to revoke.
+    pub name: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for RevokeTokenForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[debug_handler]
+async fn account_get_handler(State(state): State<AppState>, user: User) -> Result<TemplatedResponse, ErrorResponse> {
+    if user == User::Anonymous {
+        return Err(ErrorResponse::unauthenticated());
+    }
+
+    let account_config = state.accounts.load().await?;
+    Ok(account_response(&account_config, &user, false, None))
+}
+
+#[debug_handler]
+async fn change_password_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    user: User,
+    form: Form<ChangePasswordForm>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    if user == User::Anonymous {
+        return Err(ErrorResponse::unauthenticated());
+    }
+    if !matches!(state.config.auth_mode, AuthenticationMode::Single | AuthenticationMode::Multi) {
+        return Err(ErrorResponse::bad_request_with_details(
+            "Passwords are only used in the Single and Multi authentication modes.",
+        ));
+    }
+
+    // Wrong current passwords are throttled like failed logins, otherwise a stolen session could be
+    // used to guess the password.
+    let attempt = LoginAttempt::new(LoginTarget::for_user(&user), auditor.address);
+    state.throttle.begin_attempt(&attempt)?;
+
+    let mut account_config = state.accounts.load().await?;
+    let result = check_password_change(state.config.auth_mode, &state.config.account_policy, &account_config, &user, &form);
+    if !matches!(result, Err(PasswordChangeError::IncorrectPassword)) {
+        state.throttle.record_successful_attempt(&attempt);
+    }
+    if let Err(err) = result {
+        return Ok(account_response(&account_config, &user, false, Some(&err.to_string())));
+    }
+
+    set_password(&mut account_config, &user, &form.new_password, &state.config.password_hashing)?;
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::PasswordChange, Some(&user.to_string()), None).await;
+    Ok(account_response(&account_config, &user, true, None))
+}
+
+/// Why a password couldn't be changed, with a message suitable for showing the user.
+#[derive(Debug, Snafu)]
+enum PasswordChangeError {
+    #[snafu(display("The current password is incorrect."))]
+    IncorrectPassword,
+    #[snafu(display("The new passwords don't match."))]
+    Mismatch,
+    #[snafu(display("{source}"))]
+    Policy { source: PolicyViolation },
+}
+
+/// Checks that the user entered their current password, and that the new password is confirmed and
+/// follows the policy.
+fn check_password_change(
+    auth_mode: AuthenticationMode,
+    policy: &AccountPolicyConfig,
+    account_config: &AccountConfig,
+    user: &User,
+    form: &ChangePasswordForm,
+) -> Result<(), PasswordChangeError> {
+    let username = match user {
+        User::Account(username) => Some(username),
+        _ => None,
+    };
+    let verified = verify_credentials(account_config, auth_mode, username, &form.current_password);
+    ensure!(verified.as_ref() == Some(user), IncorrectPasswordSnafu);
+    ensure!(form.new_password == form.confirm_password, MismatchSnafu);
+    policy::validate_password(policy, &form.new_password, username).context(PolicySnafu)
+}
+
+#[debug_handler]
+async fn totp_get_handler(State(state): State<AppState>, user: User) -> Result<TemplatedResponse, ErrorResponse> {
+    if user == User::Anonymous {
+        return Err(ErrorResponse::unauthenticated());
+    }
+
+    let account_config = state.accounts.load().await?;
+    match account_config.totp_secret(&user) {
+        Some(_) => {
+            let mut context = context("Two-Factor Authentication");
+            context.insert("account__totp_enrolled", &true);
+            Ok(TemplatedResponse::new("account.totp.tera", context))
+        }
+        None => enrollment_response(&user, &totp::generate_secret(), None),
+    }
+}
+
+#[debug_handler]
+async fn totp_enable_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    user: User,
+    form: Form<EnableTotpForm>,
+) -> Result<Response, ErrorResponse> {
+    if user == User::Anonymous {
+        return Err(ErrorResponse::unauthenticated());
+    }
+    if !totp::validate_secret(&form.secret) {
+        return Err(ErrorResponse::bad_request_with_details("The two-factor secret is not valid."));
+    }
+
+    // Replacing the secret would get around the code needed to disable two-factor authentication.
+    let mut account_config = state.accounts.load().await?;
+    if account_config.totp_secret(&user).is_some() {
+        return Err(ErrorResponse::totp_already_enabled());
+    }
+
+    // Re-display the same secret on failure, so that the user doesn't have to enroll again.
+    if !state.used_codes.verify(&user, &form.secret, &form.code) {
+        let error = "The code is incorrect. Make sure the time on your device is correct and try again.";
+        return enrollment_response(&user, &form.secret, Some(error)).map(IntoResponse::into_response);
+    }
+
+    set_totp_secret(&mut account_config, &user, Some(form.secret.clone()))?;
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::TotpEnable, Some(&user.to_string()), None).await;
+    Ok(Redirect::to("/special:account:totp").into_response())
+}
+
+#[debug_handler]
+async fn totp_disable_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    user: User,
+    form: Form<DisableTotpForm>,
+) -> Result<Redirect, ErrorResponse> {
+    if user == User::Anonymous {
+        return Err(ErrorResponse::unauthenticated());
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    let verified = account_config
+        .totp_secret(&user)
+        .is_some_and(|secret| state.used_codes.verify(&user, secret, &form.code));
+    if !verified {
+        return Err(ErrorResponse::invalid_totp_code());
+    }
+
+    set_totp_secret(&mut account_config, &user, None)?;
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::TotpDisable, Some(&user.to_string()), None).await;
+    Ok(Redirect::to("/special:account:totp"))
+}
+
+#[debug_handler]
+async fn tokens_get_handler(State(state): State<AppState>, user: User) -> Result<TemplatedResponse, ErrorResponse> {
+    let account_config = state.accounts.load().await?;
+    tokens_response(&account_config, &user, None)
+}
+
+#[debug_handler]
+async fn create_token_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    user: User,
+    form: Form<CreateTokenForm>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(ErrorResponse::bad_request_with_details("A token name is required."));
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    let tokens = match account_config.tokens_mut(&user) {
+        Some(tokens) => tokens,
+        None => return Err(no_tokens(&user)),
+    };
+    if tokens.iter().any(|token| token.name == name) {
+        return Err(ErrorResponse::conflict(
+            "Token Already Exists",
+            format!("A token with the name `{name}` already exists."),
+        ));
+    }
+
+    let (token, secret) = ApiToken::generate(name.to_owned());
+    tokens.push(token);
+    state.accounts.save(&account_config).await?;
+    auditor
+        .record(AuditAction::TokenCreate, Some(&user.to_string()), Some(format!("Token `{name}`")))
+        .await;
+
+    // The token itself is only shown this once, since only its hash is kept.
+    tokens_response(&account_config, &user, Some(&secret))
+}
+
+#[debug_handler]
+async fn revoke_token_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    user: User,
+    form: Form<RevokeTokenForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let mut account_config = state.accounts.load().await?;
+    let tokens = match account_config.tokens_mut(&user) {
+        Some(tokens) => tokens,
+        None => return Err(no_tokens(&user)),
+    };
+    let count = tokens.len();
+    tokens.retain(|token| token.name != form.name);
+    if tokens.len() == count {
+        return Ok(Redirect::to("/special:account:tokens"));
+    }
+    state.accounts.save(&account_config).await?;
+    auditor
+        .record(AuditAction::TokenRevoke, Some(&user.to_string()), Some(format!("Token `{}`", form.name)))
+        .await;
+    Ok(Redirect::to("/special:account:tokens"))
+}
+
+fn account_response(
+    account_config: &AccountConfig,
+    user: &User,
+    password_changed: bool,
+    password_error: Option<&str>,
+) -> TemplatedResponse {
+    let mut context = context("Account");
+    context.insert("account__has_tokens", &account_config.tokens(user).is_some());
+    context.insert("account__password_changed", &password_changed);
+    context.insert("account__password_error", &password_error);
+    TemplatedResponse::new("account.tera", context)
+}
+
+fn tokens_response(
+    account_config: &AccountConfig,
+    user: &User,
+    new_token: Option<&str>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let tokens = match account_config.tokens(user) {
+        Some(tokens) => tokens,
+        None => return Err(no_tokens(user)),
+    };
+
+    let mut context = context("API Tokens");
+    context.insert("account__tokens", tokens);
+    context.insert("account__new_token", &new_token);
+    Ok(TemplatedResponse::new("account.tokens.tera", context))
+}
+
+/// The error for a user who can't have API tokens, such as a proxy user without an account.
+fn no_tokens(user: &User) -> ErrorResponse {
+    match user {
+        User::Anonymous => ErrorResponse::unauthenticated(),
+        _ => ErrorResponse::tokens_need_account(),
+    }
+}
+
+fn enrollment_response(user: &User, secret: &str, error: Option<&str>) -> Result<TemplatedResponse, ErrorResponse> {
+    let uri = match totp::otpauth_uri(secret, &user.to_string()) {
+        Some(uri) => uri,
+        None => return Err(ErrorResponse::internal_error("Failed to build the two-factor enrollment URI.")),
+    };
+
+    let mut context = context("Two-Factor Authentication");
+    context.insert("account__totp_enrolled", &false);
+    context.insert("account__totp_secret", secret);
+    context.insert("account__totp_qr_code", &totp::qr_code_svg(&uri));
+    context.insert("account__totp_uri", &uri);
+    context.insert("account__totp_error", &error);
+    Ok(TemplatedResponse::new("account.totp.tera", context))
+}
+
+/// Sets the password of the specified user.
+fn set_password(
+    account_config: &mut AccountConfig,
+    user: &User,
+    password: &str,
+    hashing: &PasswordHashingConfig,
+) -> Result<(), ErrorResponse> {
+    match user {
+        User::Anonymous => Err(ErrorResponse::unauthenticated()),
+        User::SingleUser => {
+            account_config.single_password = Some(hash_password(password, hashing));
+            Ok(())
+        }
+        User::Account(username) => match account_config.find_by_username_mut(username) {
+            Some(acc) => {
+                acc.set_password(password, hashing);
+                Ok(())
+            }
+            None => Err(ErrorResponse::account_not_found(username)),
+        },
+    }
+}
+
+/// Sets or removes the TOTP secret of the specified user.
+pub(crate) fn set_totp_secret(
+    account_config: &mut AccountConfig,
+    user: &User,
+    secret: Option<String>,
+) -> Result<(), ErrorResponse> {
+    match user {
+        User::Anonymous => Err(ErrorResponse::unauthenticated()),
+        User::SingleUser => {
+            account_config.single_totp_secret = secret;
+            Ok(())
+        }
+        User::Account(username) => match account_config.find_by_username_mut(username) {
+            Some(acc) => {
+                acc.set_totp_secret(secret);
+                Ok(())
+            }
+            None => Err(ErrorResponse::account_not_found(username)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::account::*;
+
+    #[test]
+    fn change_password_checks() {
+        let hashing = PasswordHashingConfig::default();
+        let mut account_config = AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: vec![Account::new("alex".into(), "correct horse battery staple", &hashing)],
+            used_invites: Vec::new(),
+        };
+        let policy = AccountPolicyConfig::default();
+        let user = User::Account("alex".into());
+        let form = |current: &str, new: &str, confirm: &str| ChangePasswordForm {
+            current_password: current.to_owned(),
+            new_password: new.to_owned(),
+            confirm_password: confirm.to_owned(),
+            session_id: String::new(),
+        };
+        let check = |account_config: &AccountConfig, form: &ChangePasswordForm| {
+            check_password_change(AuthenticationMode::Multi, &policy, account_config, &user, form)
+        };
+
+        let err = check(&account_config, &form("wrong password", "plaid tuba sunrise", "plaid tuba sunrise"));
+        assert!(matches!(err, Err(PasswordChangeError::IncorrectPassword)));
+        let err = check(&account_config, &form("correct horse battery staple", "plaid tuba sunrise", "plaid tuba"));
+        assert!(matches!(err, Err(PasswordChangeError::Mismatch)));
+        let err = check(&account_config, &form("correct horse battery staple", "password", "password"));
+        assert!(matches!(err, Err(PasswordChangeError::Policy { .. })));
+        // Another user's password isn't enough.
+        let single_user = check_password_change(
+            AuthenticationMode::Single,
+            &policy,
+            &account_config,
+            &User::SingleUser,
+            &form("correct horse battery staple", "plaid tuba sunrise", "plaid tuba sunrise"),
+        );
+        assert!(matches!(single_user, Err(PasswordChangeError::IncorrectPassword)));
+
+        let change = form("correct horse battery staple", "plaid tuba sunrise", "plaid tuba sunrise");
+        assert!(check(&account_config, &change).is_ok());
+        set_password(&mut account_config, &user, &change.new_password, &hashing).unwrap();
+        let account = account_config.find_by_username(&"alex".into()).unwrap();
+        assert!(account.verify_password("plaid tuba sunrise").is_ok());
+        assert!(account.verify_password("correct horse battery staple").is_err());
+    }
+}
@@ -1,9 +1,17 @@
+use crate::audit::{AuditAction, AuditFilter, Auditor};
 use crate::auth::*;
 use crate::extractors::Form;
+use crate::htpasswd;
+use crate::policy;
+use crate::references;
+use crate::routes::absolute_url;
+use crate::signed::{Claims, TokenPurpose};
 use crate::*;
-use axum::extract::State;
+use axum::extract::multipart::MultipartRejection;
+use axum::extract::{Multipart, State};
 use axum::response::Redirect;
 use axum::routing::post;
+use http::HeaderMap;
 use axum::{Router, debug_handler, extract, routing::get};
 use serde::Deserialize;
 
@@ -11,11 +19,19 @@ pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/special:admin", get(admin_get_handler))
         .route("/special:admin:account", get(account_get_handler))
+        .route("/special:admin:audit", get(audit_get_handler))
         .route(
             "/special:admin:add_account",
             get(add_account_get_handler).post(add_account_post_handler),
         )
+        .route("/special:admin:import", get(import_get_handler).post(import_post_handler))
+        .route("/special:admin:invite", post(invite_post_handler))
         .route("/special:admin:change_password", post(change_password_post_handler))
+        .route("/special:admin:reset_link", post(reset_link_post_handler))
+        .route("/special:admin:reset_totp", post(reset_totp_post_handler))
+        .route("/special:admin:disable_account", post(disable_account_post_handler))
+        .route("/special:admin:rename_account", post(rename_account_post_handler))
+        .route("/special:admin:delete_account", post(delete_account_post_handler))
         .with_state(state.clone())
         .layer(from_fn_with_state(state, authorize_middleware))
 }
@@ -25,6 +41,18 @@ struct EditAccountQuery {
     pub username: Username,
 }
 
+#[derive(Deserialize)]
+struct AuditQuery {
+    /// Only shows events performed by this user.
+    pub user: Option<String>,
+    /// Only shows events with this action. Empty to show all actions.
+    pub action: Option<String>,
+    /// Only shows events affecting a path or username containing this text.
+    pub target: Option<String>,
+    /// The maximum number of events to show.
+    pub limit: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct AddAccountForm {
     /// The username of the new account.
@@ -41,6 +69,20 @@ impl AntiCsrfForm for AddAccountForm {
     }
 }
 
+#[derive(Deserialize)]
+struct InviteForm {
+    /// The username of the account the invite will create.
+    pub username: Username,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for InviteForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
 #[derive(Deserialize)]
 struct ChangePasswordForm {
     /// The username of the account to change the password of. [None] for Single-User mode.
@@ -57,6 +99,84 @@ impl AntiCsrfForm for ChangePasswordForm {
     }
 }
 
+#[derive(Deserialize)]
+struct ResetLinkForm {
+    /// The username of the account to create a password reset link for.
+    pub username: Username,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for ResetLinkForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct ResetTotpForm {
+    /// The username of the account to remove two-factor authentication from. [None] for Single-User mode.
+    pub username: Option<Username>,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for ResetTotpForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct DisableAccountForm {
+    /// The username of the account to disable or enable.
+    pub username: Username,
+    /// Whether the account should be disabled.
+    pub disabled: bool,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for DisableAccountForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct RenameAccountForm {
+    /// The current username of the account.
+    pub username: Username,
+    /// The new username of the account.
+    pub new_username: Username,
+    /// Whether to replace the username in the access lists of articles. Set if present.
+    pub rewrite_references: Option<String>,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for RenameAccountForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteAccountForm {
+    /// The username of the account to delete.
+    pub username: Username,
+    /// Whether to remove the username from the access lists of articles. Set if present.
+    pub rewrite_references: Option<String>,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for DeleteAccountForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
 async fn authorize_middleware(
     State(state): State<AppState>,
     session: Session,
@@ -71,100 +191,463 @@ async fn authorize_middleware(
 }
 
 #[debug_handler]
-async fn admin_get_handler() -> Result<TemplatedResponse, ErrorResponse> {
-    let account_config = load_account_config().await.map_err(|err| err)?;
-    let accounts = account_config.accounts.iter().map(|acc| &acc.username).collect::<Vec<_>>();
-
-    let mut context = context("Admin");
-    context.insert("admin__accounts", &accounts);
+async fn admin_get_handler(State(state): State<AppState>) -> Result<TemplatedResponse, ErrorResponse> {
+    admin_response(&state, None).await
+}
 
-    Ok(TemplatedResponse::new("admin.tera", context))
+#[debug_handler]
+async fn account_get_handler(
+    State(state): State<AppState>,
+    query: extract::Query<EditAccountQuery>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    account_response(&state, &query.username, AccountErrors::default()).await
 }
 
+/// Shows the most recent events in the audit log matching the query, newest first.
 #[debug_handler]
-async fn account_get_handler(query: extract::Query<EditAccountQuery>) -> Result<TemplatedResponse, ErrorResponse> {
-    let account_config = load_account_config().await.map_err(|err| err)?;
-    let account = match account_config.find_by_username(&query.username) {
-        None => return Err(ErrorResponse::account_not_found(&query.username)),
-        Some(acc) => acc,
+async fn audit_get_handler(
+    State(state): State<AppState>,
+    query: extract::Query<AuditQuery>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let action = match query.action.as_deref().filter(|action| !action.is_empty()) {
+        Some(name) => match AuditAction::from_name(name) {
+            Some(action) => Some(action),
+            None => return Err(ErrorResponse::bad_request_with_details(format!("Unknown action `{name}`."))),
+        },
+        None => None,
+    };
+    let filter = AuditFilter {
+        user: query.user.clone(),
+        actions: action.into_iter().collect(),
+        target: query.target.clone(),
     };
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
 
-    let mut context = context("Editing Account");
-    context.insert("admin__username", &account.username);
-    Ok(TemplatedResponse::new("admin.account.tera", context))
+    let events = match state.audit.find(&filter, limit).await {
+        Ok(events) => events,
+        Err(err) => return Err(ErrorResponse::internal_error(format!("Failed to read the audit log: {err}"))),
+    };
+    let events = events
+        .iter()
+        .map(|event| {
+            serde_json::json!({
+                "time": event.time,
+                "user": event.user.to_string(),
+                "address": event.address,
+                "action": event.action,
+                "target": event.target,
+                "details": event.details,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut context = context("Audit Log");
+    context.insert("admin__events", &events);
+    context.insert("admin__actions", AuditAction::ALL);
+    context.insert("admin__user", &query.user);
+    context.insert("admin__action", &action);
+    context.insert("admin__target", &query.target);
+    context.insert("admin__limit", &limit);
+    Ok(TemplatedResponse::new("admin.audit.tera", context))
 }
 
 #[debug_handler]
 async fn add_account_get_handler() -> TemplatedResponse {
-    TemplatedResponse::new("admin.add_account.tera", context("Add Account"))
+    add_account_response(None, None)
 }
 
 #[debug_handler]
 async fn add_account_post_handler(
-    State(_): State<AppState>,
+    State(state): State<AppState>,
+    auditor: Auditor,
     session: Session,
     form: Form<AddAccountForm>,
-) -> Result<Redirect, ErrorResponse> {
+) -> Result<Response, ErrorResponse> {
     // Validate the session ID to prevent CSRF attacks.
     if !form.is_valid(session.id.as_deref()) {
         return Err(ErrorResponse::bad_request());
     }
 
-    let mut account_config = match load_account_config().await {
+    let mut account_config = match state.accounts.load().await {
         Ok(config) => config,
         Err(err) => return Err(err),
     };
 
-    match account_config.find_by_username_mut(&form.username) {
-        None => account_config
-            .accounts
-            .push(Account::new(form.username.clone(), &form.password)),
-        Some(acc) => {
-            return Err(ErrorResponse::conflict(
-                "Account Already Exists",
-                format!("An account with the username `{}` already exists.", acc.username),
-            ));
+    let policy = &state.config.account_policy;
+    let validation = policy::validate_username(policy, &account_config, &form.username, None)
+        .and_then(|_| policy::validate_password(policy, &form.password, Some(&form.username)));
+    if let Err(violation) = validation {
+        let error = violation.to_string();
+        return Ok(add_account_response(Some(&form.username), Some(&error)).into_response());
+    }
+
+    account_config
+        .accounts
+        .push(Account::new(form.username.clone(), &form.password, &state.config.password_hashing));
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::AccountCreate, Some(form.username.as_str()), None).await;
+    Ok(Redirect::to("/").into_response())
+}
+
+#[debug_handler]
+async fn import_get_handler() -> TemplatedResponse {
+    import_response(None, &[])
+}
+
+/// Imports accounts from an uploaded htpasswd file, keeping each user's existing password hash.
+#[debug_handler]
+async fn import_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    session: Session,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let mut multipart = multipart?;
+    let mut session_id = None;
+    let mut contents = None;
+    while let Some(field) = multipart.next_field().await? {
+        match field.name() {
+            Some("session_id") => session_id = Some(field.text().await?),
+            Some("htpasswd") => contents = Some(field.text().await?),
+            _ => {}
         }
+    }
+    // File uploads aren't read by the Form extractor, so the session ID is validated here instead.
+    if session_id.is_none() || session_id != session.id {
+        return Err(ErrorResponse::bad_request());
+    }
+    let Some(contents) = contents else {
+        return Err(ErrorResponse::bad_request_with_details("No htpasswd file was uploaded."));
     };
 
-    save_account_config(&account_config)
-        .await
-        .map_or_else(|err| Err(err), |_| Ok(Redirect::to("/")))
+    let mut account_config = state.accounts.load().await?;
+    let report = htpasswd::import(&contents, &state.config.account_policy, &mut account_config);
+    if !report.imported.is_empty() {
+        state.accounts.save(&account_config).await?;
+    }
+    for username in &report.imported {
+        let details = Some("Imported from an htpasswd file".to_owned());
+        auditor.record(AuditAction::AccountCreate, Some(username.as_str()), details).await;
+    }
+
+    let skipped = report.skipped.iter().map(ToString::to_string).collect::<Vec<_>>();
+    Ok(import_response(Some(&report.imported), &skipped))
+}
+
+/// Creates an invite link, letting the recipient create the account with a password of their own.
+#[debug_handler]
+async fn invite_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    headers: HeaderMap,
+    form: Form<InviteForm>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    if state.config.auth_mode != AuthenticationMode::Multi {
+        return Err(ErrorResponse::bad_request_with_details(
+            "Invites are only available in the Multi authentication mode.",
+        ));
+    }
+
+    let account_config = state.accounts.load().await?;
+    if let Err(violation) = policy::validate_username(&state.config.account_policy, &account_config, &form.username, None) {
+        return Ok(add_account_response(Some(&form.username), Some(&violation.to_string())));
+    }
+
+    let claims = Claims::new(TokenPurpose::Invite, form.username.clone());
+    let token = claims.sign(&state.config.secret_key);
+    let link = absolute_url(&headers, &format!("/special:invite?token={token}"));
+    auditor.record(AuditAction::InviteCreate, Some(claims.username.as_str()), None).await;
+
+    let message = format!(
+        "Send this link to the person the account {} is for. It can be used once, and expires in {} days.",
+        claims.username,
+        TokenPurpose::Invite.lifetime_seconds() / 86400
+    );
+    Ok(link_response("Invite Created", &message, &link))
+}
+
+/// Creates a password reset link, letting the user choose a new password without the administrator
+/// knowing it.
+#[debug_handler]
+async fn reset_link_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    headers: HeaderMap,
+    form: Form<ResetLinkForm>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let account_config = state.accounts.load().await?;
+    let account = match account_config.find_by_username(&form.username) {
+        Some(acc) => acc,
+        None => return Err(ErrorResponse::account_not_found(&form.username)),
+    };
+
+    let claims = Claims::new(TokenPurpose::PasswordReset, account.username.clone())
+        .with_fingerprint(account.password_fingerprint());
+    let token = claims.sign(&state.config.secret_key);
+    let link = absolute_url(&headers, &format!("/special:reset?token={token}"));
+    auditor
+        .record(AuditAction::PasswordResetLinkCreate, Some(account.username.as_str()), None)
+        .await;
+
+    let message = format!(
+        "Send this link to {}. It can be used once to choose a new password, and expires in {} hours or when the \
+         password is changed.",
+        account.username,
+        TokenPurpose::PasswordReset.lifetime_seconds() / 3600
+    );
+    Ok(link_response("Password Reset Link Created", &message, &link))
 }
 
 #[debug_handler]
 async fn change_password_post_handler(
-    State(_): State<AppState>,
+    State(state): State<AppState>,
+    auditor: Auditor,
     form: Form<ChangePasswordForm>,
-) -> Result<Redirect, ErrorResponse> {
-    let mut account_config = match AccountConfig::from_file("accounts.toml").await {
-        Ok(config) => config,
-        Err(err) => return Err(ErrorResponse::from(err)),
-    };
+) -> Result<Response, ErrorResponse> {
+    let mut account_config = state.accounts.load().await?;
+
+    if let Err(violation) = policy::validate_password(&state.config.account_policy, &form.password, form.username.as_ref()) {
+        let error = violation.to_string();
+        let response = match &form.username {
+            Some(username) => {
+                let errors = AccountErrors {
+                    password: Some(&error),
+                    ..Default::default()
+                };
+                account_response(&state, username, errors).await?
+            }
+            None => admin_response(&state, Some(&error)).await?,
+        };
+        return Ok(response.into_response());
+    }
 
     match &form.username {
         Some(username) => match account_config.find_by_username_mut(username) {
-            Some(acc) => acc.set_password(&form.password),
+            Some(acc) => acc.set_password(&form.password, &state.config.password_hashing),
             None => return Err(ErrorResponse::account_not_found(username)),
         },
-        None => account_config.single_password = Some(hash_password(&form.password)),
+        None => account_config.single_password = Some(hash_password(&form.password, &state.config.password_hashing)),
+    }
+
+    state.accounts.save(&account_config).await?;
+    let target = match &form.username {
+        Some(username) => User::Account(username.clone()),
+        None => User::SingleUser,
+    };
+    auditor.record(AuditAction::PasswordChange, Some(&target.to_string()), None).await;
+    Ok(Redirect::to("/").into_response())
+}
+
+/// Removes two-factor authentication from an account, for when a user has lost their authenticator.
+#[debug_handler]
+async fn reset_totp_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    form: Form<ResetTotpForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let mut account_config = state.accounts.load().await?;
+    let user = match &form.username {
+        Some(username) => User::Account(username.clone()),
+        None => User::SingleUser,
+    };
+    crate::routes::account::set_totp_secret(&mut account_config, &user, None)?;
+
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::TotpDisable, Some(&user.to_string()), None).await;
+    Ok(Redirect::to("/special:admin"))
+}
+
+#[debug_handler]
+async fn disable_account_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    form: Form<DisableAccountForm>,
+) -> Result<Redirect, ErrorResponse> {
+    if form.disabled && auditor.user == User::Account(form.username.clone()) {
+        return Err(ErrorResponse::bad_request_with_details("You can't disable your own account."));
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    match account_config.find_by_username_mut(&form.username) {
+        Some(acc) => acc.disabled = form.disabled,
+        None => return Err(ErrorResponse::account_not_found(&form.username)),
+    }
+    state.accounts.save(&account_config).await?;
+    let action = match form.disabled {
+        true => AuditAction::AccountDisable,
+        false => AuditAction::AccountEnable,
+    };
+    auditor.record(action, Some(form.username.as_str()), None).await;
+    Ok(Redirect::to("/special:admin"))
+}
+
+#[debug_handler]
+async fn rename_account_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    form: Form<RenameAccountForm>,
+) -> Result<Response, ErrorResponse> {
+    let mut account_config = state.accounts.load().await?;
+    let new_username = &form.new_username;
+    if let Err(violation) =
+        policy::validate_username(&state.config.account_policy, &account_config, new_username, Some(&form.username))
+    {
+        let error = violation.to_string();
+        let errors = AccountErrors {
+            rename: Some(&error),
+            ..Default::default()
+        };
+        return Ok(account_response(&state, &form.username, errors).await?.into_response());
+    }
+
+    match account_config.find_by_username_mut(&form.username) {
+        Some(acc) => acc.username = new_username.clone(),
+        None => return Err(ErrorResponse::account_not_found(&form.username)),
     }
+    state.accounts.save(&account_config).await?;
+    auditor
+        .record(AuditAction::AccountRename, Some(form.username.as_str()), Some(format!("Renamed to `{new_username}`")))
+        .await;
 
-    save_account_config(&account_config)
-        .await
-        .map_or_else(|err| Err(err), |_| Ok(Redirect::to("/")))
+    let message = format!("The account {} was renamed to {new_username}.", form.username);
+    let rewrite = form.rewrite_references.is_some();
+    let response = references_response(&state, &form.username, Some(new_username), rewrite, message).await?;
+    Ok(response.into_response())
 }
 
-async fn load_account_config() -> Result<AccountConfig, ErrorResponse> {
-    match AccountConfig::from_file("accounts.toml").await {
-        Ok(config) => Ok(config),
-        Err(err) => Err(ErrorResponse::from(err)),
+#[debug_handler]
+async fn delete_account_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    form: Form<DeleteAccountForm>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    if auditor.user == User::Account(form.username.clone()) {
+        return Err(ErrorResponse::bad_request_with_details("You can't delete your own account."));
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    let count = account_config.accounts.len();
+    account_config.accounts.retain(|acc| acc.username != form.username);
+    if account_config.accounts.len() == count {
+        return Err(ErrorResponse::account_not_found(&form.username));
     }
+    state.accounts.save(&account_config).await?;
+    auditor.record(AuditAction::AccountDelete, Some(form.username.as_str()), None).await;
+
+    let message = format!("The account {} was deleted.", form.username);
+    references_response(&state, &form.username, None, form.rewrite_references.is_some(), message).await
 }
 
-async fn save_account_config(config: &AccountConfig) -> Result<(), ErrorResponse> {
-    match config.write_to_file("accounts.toml").await {
-        Ok(()) => Ok(()),
-        Err(err) => Err(ErrorResponse::from(err)),
+/// Validation errors to show in the forms of the account page.
+#[derive(Default)]
+struct AccountErrors<'a> {
+    password: Option<&'a str>,
+    rename: Option<&'a str>,
+}
+
+async fn admin_response(state: &AppState, password_error: Option<&str>) -> Result<TemplatedResponse, ErrorResponse> {
+    let account_config = state.accounts.load().await?;
+    let accounts = account_config.accounts.iter().map(|acc| &acc.username).collect::<Vec<_>>();
+    let disabled_accounts = account_config
+        .accounts
+        .iter()
+        .filter(|acc| acc.disabled)
+        .map(|acc| &acc.username)
+        .collect::<Vec<_>>();
+
+    let mut context = context("Admin");
+    context.insert("admin__accounts", &accounts);
+    context.insert("admin__disabled_accounts", &disabled_accounts);
+    context.insert("admin__single_totp_enrolled", &account_config.single_totp_secret.is_some());
+    context.insert("admin__password_error", &password_error);
+
+    Ok(TemplatedResponse::new("admin.tera", context))
+}
+
+fn import_response(imported: Option<&[Username]>, skipped: &[String]) -> TemplatedResponse {
+    let mut context = context("Import Accounts");
+    context.insert("admin__imported", &imported);
+    context.insert("admin__skipped", skipped);
+    TemplatedResponse::new("admin.import.tera", context)
+}
+
+async fn account_response(
+    state: &AppState,
+    username: &Username,
+    errors: AccountErrors<'_>,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let account_config = state.accounts.load().await?;
+    let account = match account_config.find_by_username(username) {
+        None => return Err(ErrorResponse::account_not_found(username)),
+        Some(acc) => acc,
+    };
+    let article_references = references::find_article_references(&state.config.articles, &account.username).await?;
+    let config_references = references::find_config_references(&state.config, &account.username);
+
+    let mut context = context("Editing Account");
+    context.insert("admin__username", &account.username);
+    context.insert("admin__totp_enrolled", &account.totp_secret().is_some());
+    context.insert("admin__disabled", &account.disabled);
+    context.insert("admin__article_references", &article_references);
+    context.insert("admin__config_references", &config_references);
+    context.insert("admin__password_error", &errors.password);
+    context.insert("admin__rename_error", &errors.rename);
+    Ok(TemplatedResponse::new("admin.account.tera", context))
+}
+
+fn link_response(title: &str, message: &str, link: &str) -> TemplatedResponse {
+    let mut context = context(title);
+    context.insert("admin__message", message);
+    context.insert("admin__link", link);
+    TemplatedResponse::new("admin.link.tera", context)
+}
+
+fn add_account_response(username: Option<&Username>, error: Option<&str>) -> TemplatedResponse {
+    let mut context = context("Add Account");
+    context.insert("admin__username", &username);
+    context.insert("admin__error", &error);
+    TemplatedResponse::new("admin.add_account.tera", context)
+}
+
+/// Rewrites the access lists of articles referencing a renamed or deleted username if requested, and
+/// reports the rewritten articles along with any references that still need attention.
+async fn references_response(
+    state: &AppState,
+    old: &Username,
+    new: Option<&Username>,
+    rewrite: bool,
+    message: String,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let rewritten = match rewrite {
+        true => rewrite_references(state, old, new).await?,
+        false => Vec::new(),
+    };
+    let article_references = references::find_article_references(&state.config.articles, old).await?;
+    let config_references = references::find_config_references(&state.config, old);
+
+    let mut context = context("Account References");
+    context.insert("admin__message", &message);
+    context.insert("admin__username", old);
+    context.insert("admin__rewritten", &rewritten);
+    context.insert("admin__article_references", &article_references);
+    context.insert("admin__config_references", &config_references);
+    Ok(TemplatedResponse::new("admin.references.tera", context))
+}
+
+/// Replaces the username in the access lists of every article, or removes it if there's no
+/// replacement. The changed articles are indexed again, as when they're saved from the editor.
+/// Returns the URL paths of the changed articles.
+pub(crate) async fn rewrite_references(
+    state: &AppState,
+    old: &Username,
+    new: Option<&Username>,
+) -> Result<Vec<String>, ErrorResponse> {
+    let rewritten = references::rewrite_article_references(&state.config.articles, old, new).await?;
+    for (url_path, article) in &rewritten {
+        state.backlinks.update(&state.titles, url_path, article);
+    }
+    if !rewritten.is_empty() {
+        state.render_cache.clear();
     }
+    Ok(rewritten.into_iter().map(|(url_path, _)| url_path).collect())
 }
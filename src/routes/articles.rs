@@ -1,6 +1,9 @@
 use crate::article::RawArticle;
+use crate::audit::{AuditAction, Auditor};
+use crate::cache::{FileStamp, Source};
 use crate::auth::*;
 use crate::extractors::Form;
+use crate::markdown::ArticleRenderer;
 use crate::responses::TemplatedResponse;
 use crate::*;
 use axum::extract;
@@ -20,6 +23,15 @@ pub fn router(state: AppState) -> Router {
 #[derive(Deserialize)]
 struct ArticleQuery {
     pub edit: Option<String>,
+    /// Returns the raw CommonMark of the article instead of rendering it.
+    pub raw: Option<String>,
+}
+
+/// Prefills the article creation form, such as when following a link to a missing article.
+#[derive(Deserialize)]
+struct CreateQuery {
+    pub path: Option<String>,
+    pub title: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -29,6 +41,8 @@ struct EditForm {
     pub edit_access: Access,
     pub cmark: String,
     /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    /// Not required when authenticating with an API token.
+    #[serde(default)]
     pub session_id: String,
 }
 
@@ -46,6 +60,8 @@ struct CreateForm {
     pub edit_access: Access,
     pub cmark: String,
     /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    /// Not required when authenticating with an API token.
+    #[serde(default)]
     pub session_id: String,
 }
 
@@ -56,7 +72,7 @@ impl AntiCsrfForm for CreateForm {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
-struct ArticlePaths {
+pub(crate) struct ArticlePaths {
     pub url: String,
     pub md: PathBuf,
 }
@@ -67,41 +83,51 @@ async fn get_handler(
     extract::Path(path): extract::Path<String>,
     query: extract::Query<ArticleQuery>,
     user: User,
-) -> Result<TemplatedResponse, ErrorResponse> {
-    let pathset = match get_paths(&state.config, &path) {
+) -> Result<Response, ErrorResponse> {
+    let pathset = match get_paths(&state.config.articles, &path) {
         None => return Err(ErrorResponse::path_not_found(&path)),
         Some(paths) => paths,
     };
 
+    // Stamped before reading, so that a change made while reading isn't missed by the render cache.
+    let stamp = FileStamp::of(&pathset.md).await;
     let raw = match RawArticle::read_from_path(&pathset.md, &pathset.url).await {
         Ok(raw) => raw,
         Err(err) => return Err(ErrorResponse::from(err)),
     };
 
-    let required = match &query.edit {
-        Some(_) => &raw.metadata.edit_access,
-        None => &raw.metadata.view_access,
+    // The raw markdown is only for viewing, even if `?edit` is also given.
+    let required = match (&query.raw, &query.edit) {
+        (None, Some(_)) => &raw.metadata.edit_access,
+        _ => &raw.metadata.view_access,
     };
 
-    check_access(&user, &required)?;
+    check_access(&user, required)?;
 
-    let template = match &query.edit {
-        Some(_) => "article_edit.tera",
-        None => "article.tera",
-    };
+    if query.raw.is_some() {
+        let headers = [(http::header::CONTENT_TYPE, "text/markdown; charset=utf-8")];
+        return Ok((headers, raw.markdown).into_response());
+    }
+
+    // The edit page only shows the markdown, so nothing is rendered or included.
+    if query.edit.is_some() {
+        return Ok(TemplatedResponse::new("article_edit.tera", article_context(&raw)).into_response());
+    }
 
-    let rendered = render_article(raw, template);
-    Ok(rendered)
+    let mut rendered = render_article(&state, &user, &pathset.url, raw, (pathset.md.clone(), stamp)).await?;
+    let backlinks = state.backlinks.find(&state.titles, &pathset.url, &user);
+    rendered.context.insert("backlinks", &backlinks);
+    Ok(rendered.into_response())
 }
 
 #[debug_handler]
 async fn post_handler(
     State(state): State<AppState>,
     extract::Path(path): extract::Path<String>,
-    user: User,
+    auditor: Auditor,
     form: Form<EditForm>,
 ) -> Result<Redirect, ErrorResponse> {
-    let pathset = match get_paths(&state.config, &path) {
+    let pathset = match get_paths(&state.config.articles, &path) {
         None => return Err(ErrorResponse::path_not_found(&path)),
         Some(paths) => paths,
     };
@@ -111,7 +137,7 @@ async fn post_handler(
         Err(err) => return Err(ErrorResponse::from(err)),
     };
 
-    check_access(&user, &raw.metadata.edit_access)?;
+    check_access(&auditor.user, &raw.metadata.edit_access)?;
 
     let metadata = Metadata {
         title: form.title.clone(),
@@ -125,10 +151,15 @@ async fn post_handler(
     };
 
     match raw_article.write_to_path(&pathset.md, &pathset.url).await {
-        Ok(_) => Ok(Redirect::to(&pathset.url)),
+        Ok(_) => {
+            state.backlinks.update(&state.titles, &pathset.url, &raw_article);
+            state.render_cache.clear();
+            auditor.record(AuditAction::ArticleUpdate, Some(&pathset.url), None).await;
+            Ok(Redirect::to(&pathset.url))
+        }
         Err(err) => {
             let err = ErrorResponse::from(err);
-            Err(ErrorResponse::from(err))
+            Err(err)
         }
     }
 }
@@ -137,40 +168,48 @@ async fn root_get_handler(
     State(state): State<AppState>,
     query: extract::Query<ArticleQuery>,
     user: User,
-) -> Result<TemplatedResponse, ErrorResponse> {
+) -> Result<Response, ErrorResponse> {
     get_handler(State(state), extract::Path(String::new()), query, user).await
 }
 
 async fn root_post_handler(
     State(state): State<AppState>,
-    user: User,
+    auditor: Auditor,
     form: Form<EditForm>,
 ) -> Result<Redirect, ErrorResponse> {
-    post_handler(State(state), extract::Path(String::new()), user, form).await
+    post_handler(State(state), extract::Path(String::new()), auditor, form).await
 }
 
 #[debug_handler]
-async fn create_get_handler(State(state): State<AppState>, user: User) -> Result<TemplatedResponse, ErrorResponse> {
+async fn create_get_handler(
+    State(state): State<AppState>,
+    query: extract::Query<CreateQuery>,
+    user: User,
+) -> Result<TemplatedResponse, ErrorResponse> {
     check_access(&user, &state.config.create_access)?;
 
-    let template = "article_create.tera";
-    let raw = RawArticle::default();
-    Ok(render_article(raw, template))
+    let mut raw = RawArticle::default();
+    if let Some(title) = &query.title {
+        raw.metadata.title = title.clone();
+    }
+    let mut context = article_context(&raw);
+    context.insert("path", &query.path);
+    Ok(TemplatedResponse::new("article_create.tera", context))
 }
 
 #[debug_handler]
 async fn create_post_handler(
     State(state): State<AppState>,
-    user: User,
+    auditor: Auditor,
     form: Form<CreateForm>,
 ) -> Result<Redirect, ErrorResponse> {
     let path = &form.path;
-    let pathset = match get_paths(&state.config, path) {
+    let pathset = match get_paths(&state.config.articles, path) {
         None => return Err(ErrorResponse::bad_request()),
         Some(paths) => paths,
     };
 
-    check_access(&user, &state.config.create_access)?;
+    check_access(&auditor.user, &state.config.create_access)?;
 
     let metadata = Metadata {
         title: form.title.clone(),
@@ -184,34 +223,72 @@ async fn create_post_handler(
     };
 
     match raw_article.write_to_path(&pathset.md, &pathset.url).await {
-        Ok(_) => Ok(Redirect::to(&pathset.url)),
+        Ok(_) => {
+            state.backlinks.update(&state.titles, &pathset.url, &raw_article);
+            state.render_cache.clear();
+            auditor.record(AuditAction::ArticleCreate, Some(&pathset.url), None).await;
+            Ok(Redirect::to(&pathset.url))
+        }
         Err(err) => Err(ErrorResponse::from(err)),
     }
 }
 
-fn render_article(raw: RawArticle, template: &'static str) -> TemplatedResponse {
+/// Renders an article for viewing from its file, as stamped before it was read. The rendered
+/// markdown is cached, and the page can be answered with `304 Not Modified`.
+async fn render_article(
+    state: &AppState,
+    user: &User,
+    url: &str,
+    raw: RawArticle,
+    source: Source,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    let (rendered, last_modified) = match state.render_cache.get(url, user).await {
+        Some(cached) => cached,
+        None => {
+            // Mentions depend on which accounts exist.
+            let accounts = state.accounts.path().to_path_buf();
+            let accounts_stamp = FileStamp::of(&accounts).await;
+            let account_config = state.accounts.load().await?;
+            let renderer = ArticleRenderer {
+                articles: &state.config.articles,
+                titles: &state.titles,
+                policy: &state.config.account_policy,
+                accounts: &account_config,
+                sanitizer: &state.sanitizer,
+                user,
+            };
+            let rendered = renderer.render(url, &raw).await;
+            let sources = vec![source, (accounts, accounts_stamp)];
+            let last_modified = state.render_cache.insert(url, user, sources, &rendered);
+            (rendered, last_modified)
+        }
+    };
+
+    let mut context = article_context(&raw);
+    context.insert("rendered_cmark", &rendered.html);
+    context.insert("toc", &rendered.toc);
+    context.insert("toc_inline", &rendered.toc_inline);
+
+    let mut response = TemplatedResponse::new("article.tera", context);
+    response.last_modified = Some(last_modified);
+    Ok(response)
+}
+
+/// Creates the context shared by the article pages, holding the article's metadata and markdown.
+fn article_context(raw: &RawArticle) -> tera::Context {
     let mut context = context(&raw.metadata.title);
     context.insert("view_access", raw.metadata.view_access.variant_string());
     context.insert("edit_access", raw.metadata.edit_access.variant_string());
     context.insert("raw_cmark", &raw.markdown);
-
-    let parser = pulldown_cmark::Parser::new_ext(&raw.markdown, pulldown_cmark::Options::all());
-    let mut rendered_cmark = String::new();
-    pulldown_cmark::html::push_html(&mut rendered_cmark, parser);
-    context.insert("rendered_cmark", &rendered_cmark);
-
-    TemplatedResponse::new(template, context)
+    context
 }
 
-fn get_paths(config: &Config, path: &str) -> Option<ArticlePaths> {
-    let mut relative = match validate_path(&path) {
-        None => return None,
-        Some(relative) => relative,
-    };
+pub(crate) fn get_paths(articles: &Path, path: &str) -> Option<ArticlePaths> {
+    let mut relative = validate_path(path)?;
 
     // If the path points to a directory, use the index of the directory instead
     let file_stem = {
-        let mut file_stem = config.articles.join(&relative);
+        let mut file_stem = articles.join(&relative);
         if relative.to_str().unwrap().ends_with("/") || file_stem.is_dir() {
             file_stem.push("index");
             relative.push("index");
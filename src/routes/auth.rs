@@ -1,15 +1,18 @@
+use crate::audit::{AuditAction, Auditor};
 use crate::auth::*;
 use crate::extractors::Form;
 use crate::responses::TemplatedResponse;
+use crate::authentication::{login_username, save_rehashed_password, verify_credentials};
+use crate::throttle::{LoginAttempt, LoginTarget};
 use crate::*;
 use axum::extract::State;
 use axum::routing::post;
-use axum_extra::extract::SignedCookieJar;
-use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::PrivateCookieJar;
 
 pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/special:login", get(get_handler).post(post_handler))
+        .route("/special:login:totp", get(totp_get_handler).post(totp_post_handler))
         .route("/special:logout", post(logout_handler))
         .with_state(state)
 }
@@ -20,6 +23,8 @@ pub struct LoginForm {
     pub username: Option<Username>,
     /// The password associated with the account.
     pub password: String,
+    /// Whether to stay logged in after the browser is closed. Set if present.
+    pub remember: Option<String>,
     /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
     pub session_id: String,
 }
@@ -30,23 +35,36 @@ impl AntiCsrfForm for LoginForm {
     }
 }
 
+#[derive(Deserialize)]
+pub struct TotpForm {
+    /// The current code from the user's authenticator app.
+    pub code: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for TotpForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
 #[debug_handler]
 async fn get_handler(
-    State(_): State<AppState>,
+    State(state): State<AppState>,
     session: Session,
-    jar: SignedCookieJar,
-) -> Result<(SignedCookieJar, TemplatedResponse), ErrorResponse> {
+    jar: PrivateCookieJar,
+) -> Result<(PrivateCookieJar, TemplatedResponse), ErrorResponse> {
+    let mut context = context("Login");
+    context.insert("login__remember_me", &(state.config.cookies.remember_me_days > 0));
     match (&session.user, &session.id) {
         (User::Anonymous, None) => {
-            let mut context = context("Login");
             let session = Session::new(User::Anonymous);
             context.insert("session_id", &session.id);
-            let cookie = Cookie::from(session);
-            let jar = jar.add(cookie);
+            let jar = jar.add(session.cookie(&state.config.cookies));
             Ok((jar, TemplatedResponse::new("login.tera", context)))
         }
         (User::Anonymous, Some(_)) => {
-            let mut context = context("Login");
             context.insert("session_id", &session.id);
             Ok((jar, TemplatedResponse::new("login.tera", context)))
         }
@@ -57,55 +75,131 @@ async fn get_handler(
 #[debug_handler]
 async fn post_handler(
     State(state): State<AppState>,
-    jar: SignedCookieJar,
+    auditor: Auditor,
+    session: Session,
+    jar: PrivateCookieJar,
     form: Form<LoginForm>,
-) -> Result<(SignedCookieJar, Redirect), ErrorResponse> {
-    let session = Session::from(&jar);
+) -> Result<(PrivateCookieJar, Redirect), ErrorResponse> {
     if session.user != User::Anonymous {
         return Err(ErrorResponse::already_authenticated());
     }
-    if state.config.auth_mode == AuthenticationMode::Anonymous {
+    if matches!(state.config.auth_mode, AuthenticationMode::Anonymous | AuthenticationMode::Proxy) {
         return Err(ErrorResponse::bad_request());
     }
-    let account_config = match AccountConfig::from_file("accounts.toml").await {
-        Ok(config) => config,
-        Err(err) => return Err(ErrorResponse::from(err)),
-    };
 
-    let user: Option<User> = match state.config.auth_mode {
-        AuthenticationMode::Anonymous => unreachable!(),
-        AuthenticationMode::Single => account_config
-            .single_password
-            .map_or(false, |hash| verify_password(&form.password, &hash).is_ok())
-            .then(|| User::SingleUser),
-        AuthenticationMode::Multi => match &form.username {
-            None => None,
-            Some(username) => {
-                if let Some(acc) = account_config.accounts.iter().find(|acc| &acc.username == username) {
-                    acc.verify_password(&form.password)
-                        .map_or(None, |_| Some(User::Account(acc.username.clone())))
-                } else {
-                    None
-                }
-            }
-        },
-    };
+    let mut account_config = state.accounts.load().await?;
+    let username = form
+        .username
+        .as_ref()
+        .map(|username| login_username(&state.config.account_policy, &account_config, username));
+    let target = LoginTarget::for_login(state.config.auth_mode, username.as_ref());
+    let attempt = LoginAttempt::new(target, auditor.address);
+    state.throttle.begin_attempt(&attempt)?;
 
+    let user = verify_credentials(&account_config, state.config.auth_mode, username.as_ref(), &form.password);
     let user = match user {
-        None => return Err(ErrorResponse::invalid_credentials().into()),
+        None => {
+            let username = form.username.as_ref().map(Username::as_str);
+            auditor
+                .record(AuditAction::LoginFailure, username, Some("Invalid password".to_owned()))
+                .await;
+            return Err(ErrorResponse::invalid_credentials());
+        }
         Some(u) => u,
     };
+    let rehashed_password = account_config.rehashed_password(&user, &form.password, &state.config.password_hashing);
+
+    // If the user has enrolled in two-factor authentication, the login stays pending until the code
+    // is verified. Failures aren't cleared until then, otherwise the password could be used to
+    // reset the lockout while guessing codes. The new hash waits for the code too.
+    if account_config.totp_secret(&user).is_some() {
+        state.throttle.record_partial_attempt(&attempt);
+        let session = Session::pending(user).with_remember(form.remember.is_some());
+        if let Some(hash) = rehashed_password {
+            state.pending_rehashes.insert(&session, hash);
+        }
+        let jar = jar.add(session.cookie(&state.config.cookies));
+        return Ok((jar, Redirect::to("/special:login:totp")));
+    }
 
-    let session = Session::new(user);
-    let cookie = Cookie::from(session);
-    let jar = jar.add(cookie);
+    state.throttle.record_successful_attempt(&attempt);
+    save_rehashed_password(&state, &mut account_config, &user, rehashed_password).await;
+    auditor.record_as(user.clone(), AuditAction::LoginSuccess, None, None).await;
+
+    let session = Session::new(user).with_remember(form.remember.is_some());
+    let jar = jar.add(session.cookie(&state.config.cookies));
     Ok((jar, Redirect::to("/")))
 }
 
 #[debug_handler]
-async fn logout_handler(State(_state): State<AppState>, mut jar: SignedCookieJar) -> (SignedCookieJar, Redirect) {
-    if let Some(cookie) = jar.get("user") {
-        jar = jar.remove(cookie)
+async fn totp_get_handler(State(_): State<AppState>, session: Session) -> Result<Response, ErrorResponse> {
+    if session.user != User::Anonymous {
+        return Err(ErrorResponse::already_authenticated());
+    }
+
+    match session.pending {
+        Some(pending) if !pending.is_expired() => {
+            let mut context = context("Two-Factor Authentication");
+            context.insert("session_id", &session.id);
+            Ok(TemplatedResponse::new("login.totp.tera", context).into_response())
+        }
+        _ => Ok(Redirect::to("/special:login").into_response()),
+    }
+}
+
+#[debug_handler]
+async fn totp_post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    session: Session,
+    jar: PrivateCookieJar,
+    form: Form<TotpForm>,
+) -> Result<(PrivateCookieJar, Redirect), ErrorResponse> {
+    if session.user != User::Anonymous {
+        return Err(ErrorResponse::already_authenticated());
+    }
+    let pending = match session.pending {
+        Some(pending) if !pending.is_expired() => pending,
+        _ => return Err(ErrorResponse::login_expired()),
+    };
+
+    let attempt = LoginAttempt::new(LoginTarget::for_user(&pending.user), auditor.address);
+    state.throttle.begin_attempt(&attempt)?;
+
+    let mut account_config = state.accounts.load().await?;
+
+    // The secret may have been removed since the password was verified, in which case the code
+    // can't be verified either.
+    let verified = account_config
+        .totp_secret(&pending.user)
+        .is_some_and(|secret| state.used_codes.verify(&pending.user, secret, &form.code));
+    if !verified {
+        let username = pending.user.to_string();
+        auditor
+            .record(AuditAction::LoginFailure, Some(&username), Some("Invalid two-factor code".to_owned()))
+            .await;
+        return Err(ErrorResponse::invalid_totp_code());
+    }
+    state.throttle.record_successful_attempt(&attempt);
+    let rehashed_password = session.id.as_deref().and_then(|id| state.pending_rehashes.take(id));
+    save_rehashed_password(&state, &mut account_config, &pending.user, rehashed_password).await;
+    auditor.record_as(pending.user.clone(), AuditAction::LoginSuccess, None, None).await;
+
+    let session = Session::new(pending.user).with_remember(session.remember);
+    let jar = jar.add(session.cookie(&state.config.cookies));
+    Ok((jar, Redirect::to("/")))
+}
+
+#[debug_handler]
+async fn logout_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    jar: PrivateCookieJar,
+) -> (PrivateCookieJar, Redirect) {
+    // Removed even if it can't be read, so that no older cookie survives.
+    let jar = jar.remove(Session::removal_cookie(&state.config.cookies));
+    if auditor.user != User::Anonymous {
+        auditor.record(AuditAction::Logout, None, None).await;
     }
 
     (jar, Redirect::to("/"))
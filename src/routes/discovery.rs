@@ -1,13 +1,24 @@
 use crate::article::ArticleReadError;
 use crate::auth::*;
 use crate::responses::TemplatedResponse;
+use crate::routes::articles::get_paths;
 use crate::*;
+use axum::extract;
 use axum::extract::State;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 
 pub fn router(state: AppState) -> Router {
-    Router::new().route("/special:tree", get(tree_handler)).with_state(state)
+    Router::new()
+        .route("/special:tree", get(tree_handler))
+        .route("/special:backlinks", get(backlinks_handler))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct BacklinksQuery {
+    /// The path of the article to find links to.
+    pub path: String,
 }
 
 #[derive(Serialize)]
@@ -27,6 +38,7 @@ pub struct FileNode {
 }
 
 #[derive(Debug, Snafu)]
+#[allow(clippy::enum_variant_names)]
 pub enum DiscoveryTreeError {
     DirectoryOpenError { source: std::io::Error },
     EntryOpenError { source: std::io::Error },
@@ -60,11 +72,35 @@ async fn tree_handler(State(state): State<AppState>, user: User) -> Result<Templ
     Ok(TemplatedResponse::new("discovery.tree.tera", context))
 }
 
-async fn recurse_directory(article_root: &Path, mut parent: &mut DirectoryNode) -> Result<(), DiscoveryTreeError> {
+/// Lists the articles linking to an article, including articles that don't exist yet.
+#[debug_handler]
+async fn backlinks_handler(
+    State(state): State<AppState>,
+    query: extract::Query<BacklinksQuery>,
+    user: User,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    check_access(&user, &state.config.discovery_access)?;
+
+    let Some(paths) = get_paths(&state.config.articles, &query.path) else {
+        return Err(ErrorResponse::path_not_found(&query.path));
+    };
+    // Listing the links to an article shows as much as the article itself.
+    if let Some(target) = state.titles.get(&paths.url) {
+        check_access(&user, &target.view_access)?;
+    }
+    let backlinks = state.backlinks.find(&state.titles, &paths.url, &user);
+
+    let mut context = context("What Links Here");
+    context.insert("discovery__url", &paths.url);
+    context.insert("discovery__backlinks", &backlinks);
+    Ok(TemplatedResponse::new("discovery.backlinks.tera", context))
+}
+
+async fn recurse_directory(article_root: &Path, parent: &mut DirectoryNode) -> Result<(), DiscoveryTreeError> {
     // We populate and recurse separately - this ensures we only have one file handle at a time
-    populate_directory(article_root, &mut parent).await?;
-    for mut dir in &mut parent.directories {
-        Box::pin(recurse_directory(article_root, &mut dir)).await?;
+    populate_directory(article_root, parent).await?;
+    for dir in &mut parent.directories {
+        Box::pin(recurse_directory(article_root, dir)).await?;
     }
     Ok(())
 }
@@ -0,0 +1,130 @@
+use crate::audit::{AuditAction, Auditor};
+use crate::auth::*;
+use crate::extractors::Form;
+use crate::policy;
+use crate::responses::TemplatedResponse;
+use crate::routes::ensure_session;
+use crate::signed::{Claims, TokenPurpose};
+use crate::*;
+use axum::extract::{Query, State};
+use axum_extra::extract::PrivateCookieJar;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/special:invite", get(get_handler).post(post_handler))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct InviteQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct AcceptInviteForm {
+    /// The signed invite token from the link.
+    pub token: String,
+    /// The password chosen for the new account.
+    pub password: String,
+    /// The password again, to catch typos.
+    pub confirm_password: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for AcceptInviteForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[debug_handler]
+async fn get_handler(
+    State(state): State<AppState>,
+    session: Session,
+    jar: PrivateCookieJar,
+    query: Query<InviteQuery>,
+) -> Result<(PrivateCookieJar, TemplatedResponse), ErrorResponse> {
+    if session.user != User::Anonymous {
+        return Err(ErrorResponse::already_authenticated());
+    }
+    let claims = verify_invite(&state, &query.token).await?;
+
+    let (session_id, jar) = ensure_session(&state, &session, jar);
+
+    let mut context = invite_context(&claims, &query.token, None);
+    context.insert("session_id", &session_id);
+    Ok((jar, TemplatedResponse::new("invite.tera", context)))
+}
+
+#[debug_handler]
+async fn post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    session: Session,
+    jar: PrivateCookieJar,
+    form: Form<AcceptInviteForm>,
+) -> Result<Response, ErrorResponse> {
+    if session.user != User::Anonymous {
+        return Err(ErrorResponse::already_authenticated());
+    }
+    let claims = verify_invite(&state, &form.token).await?;
+
+    let error = match form.password == form.confirm_password {
+        false => Some("The passwords don't match.".to_owned()),
+        true => policy::validate_password(&state.config.account_policy, &form.password, Some(&claims.username))
+            .err()
+            .map(|violation| violation.to_string()),
+    };
+    if let Some(error) = error {
+        let mut context = invite_context(&claims, &form.token, Some(&error));
+        context.insert("session_id", &session.id);
+        return Ok(TemplatedResponse::new("invite.tera", context).into_response());
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    // The username is checked again in case the policy or accounts changed since the invite was issued.
+    if let Err(violation) = policy::validate_username(&state.config.account_policy, &account_config, &claims.username, None) {
+        return Err(ErrorResponse::bad_request_with_details(violation.to_string()));
+    }
+    let account = Account::new(claims.username.clone(), &form.password, &state.config.password_hashing);
+    account_config.accounts.push(account);
+    account_config.record_used_invite(claims.nonce.clone(), claims.expires);
+    state.accounts.save(&account_config).await?;
+    auditor
+        .record_as(
+            User::Account(claims.username.clone()),
+            AuditAction::AccountCreate,
+            Some(claims.username.as_str()),
+            Some("Accepted an invite".to_owned()),
+        )
+        .await;
+
+    let jar = jar.add(Session::new(User::Account(claims.username)).cookie(&state.config.cookies));
+    Ok((jar, Redirect::to("/")).into_response())
+}
+
+/// Verifies the invite token. Invites are single-use, so they can't be accepted again, even once the
+/// account they created is deleted or renamed.
+async fn verify_invite(state: &AppState, token: &str) -> Result<Claims, ErrorResponse> {
+    if state.config.auth_mode != AuthenticationMode::Multi {
+        return Err(ErrorResponse::bad_request_with_details(
+            "Invites are only available in the Multi authentication mode.",
+        ));
+    }
+
+    let claims = Claims::verify(token, &state.config.secret_key, TokenPurpose::Invite)?;
+    let account_config = state.accounts.load().await?;
+    if account_config.is_invite_used(&claims.nonce) || account_config.find_by_username(&claims.username).is_some() {
+        return Err(ErrorResponse::invite_used());
+    }
+    Ok(claims)
+}
+
+fn invite_context(claims: &Claims, token: &str, error: Option<&str>) -> tera::Context {
+    let mut context = context("Accept Invite");
+    context.insert("invite__username", &claims.username);
+    context.insert("invite__token", token);
+    context.insert("invite__error", &error);
+    context
+}
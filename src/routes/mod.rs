@@ -1,4 +1,98 @@
+use crate::AppState;
+use crate::auth::{Session, User};
+use crate::cache::FileStamp;
+use crate::config::AccountConfig;
+use crate::responses::ErrorResponse;
+use axum_extra::extract::PrivateCookieJar;
+use http::{HeaderMap, header};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub(crate) mod account;
 pub(crate) mod admin;
 pub(crate) mod articles;
 pub(crate) mod auth;
 pub(crate) mod discovery;
+pub(crate) mod invite;
+pub(crate) mod reset;
+pub(crate) mod users;
+
+/// The file accounts are stored in.
+pub(crate) const ACCOUNTS_FILE: &str = "accounts.toml";
+
+/// The accounts file, and the accounts last read from it along with the file's stamp from before it
+/// was read. Reused until the file changes, since the accounts are needed for most requests.
+pub(crate) struct AccountStore {
+    path: PathBuf,
+    cached: Mutex<Option<(FileStamp, AccountConfig)>>,
+}
+
+impl AccountStore {
+    pub fn new(path: impl Into<PathBuf>) -> AccountStore {
+        AccountStore {
+            path: path.into(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Gets the path of the accounts file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn load(&self) -> Result<AccountConfig, ErrorResponse> {
+        let stamp = FileStamp::of(&self.path).await;
+        if let Some((cached_stamp, config)) = &*self.cached.lock().expect("Accounts cache lock poisoned.")
+            && Some(*cached_stamp) == stamp
+        {
+            return Ok(config.clone());
+        }
+
+        match AccountConfig::from_file(&self.path).await {
+            Ok(config) => {
+                if let Some(stamp) = stamp {
+                    *self.cached.lock().expect("Accounts cache lock poisoned.") = Some((stamp, config.clone()));
+                }
+                Ok(config)
+            }
+            Err(err) => Err(ErrorResponse::from(err)),
+        }
+    }
+
+    pub async fn save(&self, config: &AccountConfig) -> Result<(), ErrorResponse> {
+        let result = config.write_to_file(&self.path).await;
+        // Forgotten even if writing failed, since the file may have been partly written. The stamp alone
+        // could miss a write that keeps the file's size and modification time.
+        *self.cached.lock().expect("Accounts cache lock poisoned.") = None;
+        match result {
+            Ok(()) => Ok(()),
+            Err(err) => Err(ErrorResponse::from(err)),
+        }
+    }
+}
+
+/// Builds an absolute URL to the specified path on this site, for links that are shared outside of
+/// it. The host is taken from the request, and the scheme from `X-Forwarded-Proto` if set by a
+/// reverse proxy.
+pub(crate) fn absolute_url(headers: &HeaderMap, path: &str) -> String {
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok()).unwrap_or("localhost");
+    let scheme = headers
+        .get("X-Forwarded-Proto")
+        .and_then(|scheme| scheme.to_str().ok())
+        .filter(|scheme| matches!(*scheme, "http" | "https"))
+        .unwrap_or("http");
+    format!("{scheme}://{host}{path}")
+}
+
+/// Gets the ID of the current session, starting a new anonymous session if there isn't one yet. Forms
+/// shown to anonymous users need a session ID to protect against CSRF.
+pub(crate) fn ensure_session(state: &AppState, session: &Session, jar: PrivateCookieJar) -> (String, PrivateCookieJar) {
+    match &session.id {
+        Some(id) => (id.clone(), jar),
+        None => {
+            let session = Session::new(User::Anonymous);
+            let id = session.id.clone().expect("New sessions always have an ID.");
+            (id, jar.add(session.cookie(&state.config.cookies)))
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::audit::{AuditAction, Auditor};
+use crate::auth::*;
+use crate::extractors::Form;
+use crate::policy;
+use crate::responses::TemplatedResponse;
+use crate::routes::ensure_session;
+use crate::signed::{Claims, SignedTokenError, TokenPurpose};
+use crate::*;
+use axum::extract::{Query, State};
+use axum_extra::extract::PrivateCookieJar;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/special:reset", get(get_handler).post(post_handler))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct ResetQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordForm {
+    /// The signed reset token from the link.
+    pub token: String,
+    /// The new password.
+    pub password: String,
+    /// The new password again, to catch typos.
+    pub confirm_password: String,
+    /// The ID of the current session, used to prevent CSRF attacks. Must match the ID set in the session cookie.
+    pub session_id: String,
+}
+
+impl AntiCsrfForm for ResetPasswordForm {
+    fn session(&self) -> &str {
+        &self.session_id
+    }
+}
+
+#[debug_handler]
+async fn get_handler(
+    State(state): State<AppState>,
+    session: Session,
+    jar: PrivateCookieJar,
+    query: Query<ResetQuery>,
+) -> Result<(PrivateCookieJar, TemplatedResponse), ErrorResponse> {
+    let claims = verify_reset(&state, &query.token).await?;
+    let (session_id, jar) = ensure_session(&state, &session, jar);
+
+    let mut context = reset_context(&claims, &query.token, None);
+    context.insert("session_id", &session_id);
+    Ok((jar, TemplatedResponse::new("reset.tera", context)))
+}
+
+#[debug_handler]
+async fn post_handler(
+    State(state): State<AppState>,
+    auditor: Auditor,
+    session: Session,
+    form: Form<ResetPasswordForm>,
+) -> Result<Response, ErrorResponse> {
+    let claims = verify_reset(&state, &form.token).await?;
+
+    let error = match form.password == form.confirm_password {
+        false => Some("The passwords don't match.".to_owned()),
+        true => policy::validate_password(&state.config.account_policy, &form.password, Some(&claims.username))
+            .err()
+            .map(|violation| violation.to_string()),
+    };
+    if let Some(error) = error {
+        let mut context = reset_context(&claims, &form.token, Some(&error));
+        context.insert("session_id", &session.id);
+        return Ok(TemplatedResponse::new("reset.tera", context).into_response());
+    }
+
+    let mut account_config = state.accounts.load().await?;
+    match account_config.find_by_username_mut(&claims.username) {
+        Some(acc) => acc.set_password(&form.password, &state.config.password_hashing),
+        None => return Err(ErrorResponse::account_not_found(&claims.username)),
+    }
+    state.accounts.save(&account_config).await?;
+    auditor
+        .record(AuditAction::PasswordReset, Some(claims.username.as_str()), Some("Used a reset link".to_owned()))
+        .await;
+
+    // The user isn't logged in automatically, since that would skip two-factor authentication.
+    Ok(Redirect::to("/special:login").into_response())
+}
+
+/// Verifies the reset token. Tokens are single-use, since setting the password changes the
+/// fingerprint they were issued for.
+async fn verify_reset(state: &AppState, token: &str) -> Result<Claims, ErrorResponse> {
+    let claims = Claims::verify(token, &state.config.secret_key, TokenPurpose::PasswordReset)?;
+    let account_config = state.accounts.load().await?;
+    let account = match account_config.find_by_username(&claims.username) {
+        Some(acc) if !acc.disabled => acc,
+        _ => return Err(ErrorResponse::from(SignedTokenError::InvalidSignature)),
+    };
+    if claims.fingerprint.as_deref() != Some(account.password_fingerprint().as_str()) {
+        return Err(ErrorResponse::bad_request_with_details(
+            "This link has already been used, or the password was changed since it was created.",
+        ));
+    }
+    Ok(claims)
+}
+
+fn reset_context(claims: &Claims, token: &str, error: Option<&str>) -> tera::Context {
+    let mut context = context("Reset Password");
+    context.insert("reset__username", &claims.username);
+    context.insert("reset__token", token);
+    context.insert("reset__error", &error);
+    context
+}
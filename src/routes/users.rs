@@ -0,0 +1,126 @@
+use crate::article::RawArticle;
+use crate::audit::{AuditAction, AuditFilter};
+use crate::auth::*;
+use crate::markdown::{ArticleRenderer, Mentions};
+use crate::responses::TemplatedResponse;
+use crate::routes::articles::get_paths;
+use crate::*;
+use axum::extract;
+use axum::extract::State;
+use serde::Serialize;
+
+/// The directory containing the optional profile article of each user, such as `/users/alex`.
+const PROFILE_DIRECTORY: &str = "users";
+/// The number of audit log events searched for contributions.
+const CONTRIBUTION_EVENTS: usize = 1000;
+/// The number of articles shown in each list of contributions.
+const CONTRIBUTION_LIMIT: usize = 25;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/special:user/{username}", get(profile_handler))
+        .with_state(state)
+}
+
+#[derive(Serialize)]
+struct Contribution {
+    pub url: String,
+    pub title: String,
+    /// The time of the most recent contribution to the article, in seconds since the unix epoch.
+    pub time: u64,
+}
+
+#[debug_handler]
+async fn profile_handler(
+    State(state): State<AppState>,
+    extract::Path(username): extract::Path<String>,
+    user: User,
+) -> Result<TemplatedResponse, ErrorResponse> {
+    check_access(&user, &state.config.discovery_access)?;
+
+    // Every name has a page, whether or not there's an account with it, so that the pages can't be used
+    // to find which accounts exist. For the same reason, the name is shown as it was given.
+    let shown_username = Username::from(username.clone());
+    let account_config = state.accounts.load().await?;
+    let mentions = Mentions::new(&state.config.account_policy, &account_config);
+    let username = mentions.resolve(&username).cloned().unwrap_or_else(|| Username::from(username));
+    let profile_user = User::Account(username.clone());
+
+    let filter = AuditFilter {
+        user: Some(username.to_string()),
+        actions: vec![AuditAction::ArticleCreate, AuditAction::ArticleUpdate],
+        ..Default::default()
+    };
+    let events = match state.audit.find(&filter, CONTRIBUTION_EVENTS).await {
+        Ok(events) => events,
+        Err(err) => return Err(ErrorResponse::internal_error(err.to_string())),
+    };
+    let events: Vec<_> = events.into_iter().filter(|event| event.user == profile_user).collect();
+
+    let mut edited: Vec<Contribution> = Vec::new();
+    let mut created: Vec<Contribution> = Vec::new();
+    for event in &events {
+        let Some(url) = event.target.as_deref() else {
+            continue;
+        };
+        // Articles the viewer can't see are left out, along with any that have since been removed.
+        let Some(title) = state
+            .titles
+            .get(url)
+            .filter(|article| article.is_visible_to(Some(&user)))
+            .map(|article| article.title)
+        else {
+            continue;
+        };
+
+        let contribution = || Contribution {
+            url: url.to_owned(),
+            title: title.clone(),
+            time: event.time,
+        };
+        if edited.len() < CONTRIBUTION_LIMIT && !edited.iter().any(|c| c.url == url) {
+            edited.push(contribution());
+        }
+        if event.action == AuditAction::ArticleCreate
+            && created.len() < CONTRIBUTION_LIMIT
+            && !created.iter().any(|c| c.url == url)
+        {
+            created.push(contribution());
+        }
+    }
+
+    let profile_url = format!("/{PROFILE_DIRECTORY}/{username}");
+    let profile = match read_visible_article(&state, &user, &profile_url).await {
+        Some(raw) => {
+            let renderer = ArticleRenderer {
+                articles: &state.config.articles,
+                titles: &state.titles,
+                policy: &state.config.account_policy,
+                accounts: &account_config,
+                sanitizer: &state.sanitizer,
+                user: &user,
+            };
+            Some(renderer.render(&profile_url, &raw).await.html)
+        }
+        None => None,
+    };
+
+    let mut context = context(&shown_username.to_string());
+    context.insert("user__username", &shown_username);
+    context.insert("user__is_self", &(user == profile_user));
+    context.insert("user__profile_url", &profile_url);
+    context.insert("user__profile", &profile);
+    context.insert("user__edited", &edited);
+    context.insert("user__created", &created);
+    Ok(TemplatedResponse::new("user.tera", context))
+}
+
+/// Reads the article at the specified URL, if it exists and the user can view it.
+async fn read_visible_article(state: &AppState, user: &User, url: &str) -> Option<RawArticle> {
+    let pathset = get_paths(&state.config.articles, url)?;
+    let raw = RawArticle::read_from_path(&pathset.md, &pathset.url).await.ok()?;
+    match user.check_authorization(&raw.metadata.view_access) {
+        Authorization::Authorized => Some(raw),
+        _ => None,
+    }
+}
@@ -0,0 +1,288 @@
+use crate::auth::Access;
+use crate::config::SanitizationConfig;
+use snafu::Snafu;
+use std::collections::HashSet;
+
+/// Tags whose contents are removed along with them, which can't be allowed.
+const UNSAFE_TAGS: &[&str] = &["script", "style"];
+
+/// Tags produced when rendering markdown, which ammonia doesn't allow by default.
+const RENDERED_TAGS: &[&str] = &[
+    "nav", "input", "math", "semantics", "annotation", "mrow", "mi", "mn", "mo", "mtext", "mspace", "msub", "msup",
+    "msubsup", "munder", "mover", "munderover", "mfrac", "msqrt", "mroot", "mtable", "mtr", "mtd",
+];
+
+/// Attributes produced when rendering markdown, by tag.
+const RENDERED_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("a", &["class", "aria-label"]),
+    ("h1", &["id", "class"]),
+    ("h2", &["id", "class"]),
+    ("h3", &["id", "class"]),
+    ("h4", &["id", "class"]),
+    ("h5", &["id", "class"]),
+    ("h6", &["id", "class"]),
+    ("nav", &["class"]),
+    ("p", &["class"]),
+    ("pre", &["class"]),
+    ("code", &["class"]),
+    ("span", &["class"]),
+    ("div", &["id", "class"]),
+    ("sup", &["class"]),
+    ("blockquote", &["class"]),
+    ("th", &["style"]),
+    ("td", &["style"]),
+    ("input", &["type", "checked", "disabled"]),
+    ("math", &["display"]),
+    ("annotation", &["encoding"]),
+    ("mi", &["mathvariant"]),
+    ("mo", &["movablelimits", "form", "fence"]),
+    ("mspace", &["width"]),
+    ("mover", &["accent"]),
+    ("munder", &["accentunder"]),
+    ("mfrac", &["linethickness"]),
+    ("mtable", &["columnalign"]),
+];
+
+/// Attributes markdown uses to style and link to the elements it produces, which are removed from raw HTML
+/// so that editors can't imitate those elements or take over their IDs.
+const RESERVED_ATTRIBUTES: &[&str] = &["id", "class"];
+
+#[derive(Debug, Snafu)]
+pub enum SanitizationError {
+    #[snafu(display("The `{tag}` tag can't be allowed, since its contents would be run by the browser."))]
+    UnsafeTag { tag: String },
+    #[snafu(display("The `{attribute}` attribute can't be allowed, since it's an event handler."))]
+    EventHandler { attribute: String },
+}
+
+/// Removes scripts, event handlers, and any other tags and attributes not in the allowlist from rendered HTML.
+/// Built once from the settings, since building the allowlist is slower than most articles are to sanitize.
+pub struct Sanitizer {
+    config: SanitizationConfig,
+    builder: ammonia::Builder<'static>,
+}
+
+impl Sanitizer {
+    /// Builds a sanitizer, after checking that the configured allowlist doesn't include anything that
+    /// could run scripts.
+    pub fn new(config: &SanitizationConfig) -> Result<Sanitizer, SanitizationError> {
+        if let Some(tag) = config.allowed_tags.iter().find(|tag| UNSAFE_TAGS.contains(&tag.to_lowercase().as_str())) {
+            return UnsafeTagSnafu { tag }.fail();
+        }
+        let attributes = config.allowed_attributes.values().flatten();
+        if let Some(attribute) = attributes.clone().find(|attribute| attribute.to_lowercase().starts_with("on")) {
+            return EventHandlerSnafu { attribute }.fail();
+        }
+
+        // The sanitizer is kept until the server stops, so the allowlist is leaked to lend it to the builder.
+        let allowed: &'static SanitizationConfig = Box::leak(Box::new(config.clone()));
+        let mut builder = ammonia::Builder::default();
+        builder
+            // Links to other articles are written by the same people, so they aren't marked as untrusted.
+            .link_rel(None)
+            .add_tags(RENDERED_TAGS)
+            .add_tags(&allowed.allowed_tags)
+            .filter_style_properties(HashSet::from(["text-align"]));
+        for (tag, attributes) in RENDERED_ATTRIBUTES {
+            builder.add_tag_attributes(tag, *attributes);
+        }
+        for (tag, attributes) in &allowed.allowed_attributes {
+            builder.add_tag_attributes(tag, attributes);
+        }
+        Ok(Sanitizer {
+            config: config.clone(),
+            builder,
+        })
+    }
+
+    /// The sanitizer to sanitize an article with, or [None] if its editors are trusted with raw HTML.
+    pub fn for_article(&self, edit_access: &Access) -> Option<&Sanitizer> {
+        let trusted = self.config.trust_account_editors && matches!(edit_access, Access::Accounts(_));
+        (self.config.enabled && !trusted).then_some(self)
+    }
+
+    /// Sanitizes rendered HTML.
+    pub fn clean(&self, html: &str) -> String {
+        self.builder.clean(html).to_string()
+    }
+
+    /// Removes the reserved `id` and `class` attributes from raw HTML written by an editor, unless they
+    /// were allowed in the settings. Tags must be complete, but needn't be closed.
+    pub fn remove_reserved_attributes(&self, html: &str) -> String {
+        let is_whitespace = |c: char| c.is_ascii_whitespace();
+        let mut output = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("<!--") {
+                let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            // Anything besides a start tag, such as an end tag or a lone `<`, is kept as it is.
+            let name_end = rest[1..].find(|c: char| !c.is_ascii_alphanumeric()).map_or(rest.len(), |end| end + 1);
+            if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                output.push('<');
+                rest = &rest[1..];
+                continue;
+            }
+            let tag = rest[1..name_end].to_ascii_lowercase();
+            output.push_str(&rest[..name_end]);
+            rest = &rest[name_end..];
+
+            loop {
+                let attribute = rest.trim_start_matches(|c: char| is_whitespace(c) || c == '/');
+                output.push_str(&rest[..rest.len() - attribute.len()]);
+                rest = attribute;
+                if rest.is_empty() || rest.starts_with('>') {
+                    break;
+                }
+                // Like browsers, a name may start with `=`, and a value may be surrounded by whitespace.
+                let name_end = rest[1..]
+                    .find(|c: char| is_whitespace(c) || matches!(c, '/' | '>' | '='))
+                    .map_or(rest.len(), |end| end + 1);
+                let mut end = name_end;
+                if let Some(value) = rest[name_end..].trim_start_matches(is_whitespace).strip_prefix('=') {
+                    let value = value.trim_start_matches(is_whitespace);
+                    let value_end = match value.chars().next() {
+                        Some(quote @ ('"' | '\'')) => value[1..].find(quote).map_or(value.len(), |end| end + 2),
+                        _ => value.find(|c: char| is_whitespace(c) || c == '>').unwrap_or(value.len()),
+                    };
+                    end = rest.len() - value.len() + value_end;
+                }
+                if !self.is_reserved(&tag, &rest[..name_end]) {
+                    output.push_str(&rest[..end]);
+                }
+                rest = &rest[end..];
+            }
+        }
+        output.push_str(rest);
+        output
+    }
+
+    fn is_reserved(&self, tag: &str, attribute: &str) -> bool {
+        let attribute = attribute.to_ascii_lowercase();
+        let allowed = self.config.allowed_attributes.get(tag);
+        RESERVED_ATTRIBUTES.contains(&attribute.as_str())
+            && allowed.is_none_or(|allowed| !allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(&attribute)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{AccountConfig, AccountPolicyConfig};
+    use crate::include::Includes;
+    use crate::markdown::{self, Mentions, RenderContext, WikiLinks};
+    use crate::sanitize::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sanitize_html() {
+        let sanitizer = Sanitizer::new(&SanitizationConfig::default()).unwrap();
+        assert_eq!(
+            sanitizer.clean("<p onclick=\"steal()\">Hi<script>steal()</script> <a href=\"javascript:steal()\">x</a></p>"),
+            "<p>Hi <a>x</a></p>"
+        );
+        assert_eq!(sanitizer.clean("<img src=x onerror=steal()>"), "<img src=\"x\">");
+        assert_eq!(sanitizer.clean("<iframe src=\"/video\"></iframe>"), "");
+
+        // Everything markdown produces is kept as it is.
+        let accounts = AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: Vec::new(),
+            used_invites: Vec::new(),
+        };
+        let policy = AccountPolicyConfig::default();
+        let context = RenderContext {
+            mentions: Mentions::new(&policy, &accounts),
+            wiki_links: WikiLinks::default(),
+            includes: Includes::default(),
+            sanitizer: None,
+            heading_ids: HashSet::new(),
+        };
+        let markdown = "# Title\n\n[TOC]\n\n| a |\n|:-:|\n| $x^2$ |\n\n- [x] Done\n\n```rust\nfn main() {}\n```\n";
+        let html = sanitizer.clean(&markdown::render(markdown, &context).html);
+        for kept in [
+            "<h1 id=\"title\">Title<a class=\"permalink\" href=\"#title\" aria-label=\"Permalink\">#</a></h1>",
+            "<nav class=\"toc\">",
+            "<th style=\"text-align:center\">",
+            "<math><semantics><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow><annotation encoding=\"application/x-tex\">",
+            "<input disabled=\"\" type=\"checkbox\" checked=\"\">",
+            "<pre class=\"hl-code\"><code class=\"language-rust\"><span class=\"hl-source hl-rust\">",
+        ] {
+            assert!(html.contains(kept), "{kept} was removed from {html}");
+        }
+
+        let config = SanitizationConfig {
+            allowed_tags: vec!["iframe".to_owned()],
+            allowed_attributes: HashMap::from([("iframe".to_owned(), vec!["src".to_owned()])]),
+            ..Default::default()
+        };
+        let sanitizer = Sanitizer::new(&config).unwrap();
+        assert_eq!(sanitizer.clean("<iframe src=\"/video\"></iframe>"), "<iframe src=\"/video\"></iframe>");
+        let config = SanitizationConfig {
+            allowed_tags: vec!["SCRIPT".to_owned()],
+            ..Default::default()
+        };
+        assert!(matches!(Sanitizer::new(&config), Err(SanitizationError::UnsafeTag { .. })));
+    }
+
+    #[test]
+    fn reserved_attributes() {
+        let accounts = AccountConfig {
+            single_password: None,
+            single_totp_secret: None,
+            single_tokens: Vec::new(),
+            accounts: Vec::new(),
+            used_invites: Vec::new(),
+        };
+        let policy = AccountPolicyConfig::default();
+        let render = |markdown: &str, sanitizer: &Sanitizer| {
+            let context = RenderContext {
+                mentions: Mentions::new(&policy, &accounts),
+                wiki_links: WikiLinks::default(),
+                includes: Includes::default(),
+                sanitizer: Some(sanitizer),
+                heading_ids: HashSet::new(),
+            };
+            markdown::render(markdown, &context).html
+        };
+
+        // Raw HTML can't pass for the elements markdown produces.
+        let sanitizer = Sanitizer::new(&SanitizationConfig::default()).unwrap();
+        let markdown = "# Title\n\n<nav\n  class=\"toc\" ID='title'>\n\n<a href=\"#title\" class=permalink title=\"class=x\">#</a>\n";
+        assert_eq!(
+            render(markdown, &sanitizer),
+            "<h1 id=\"title\">Title<a class=\"permalink\" href=\"#title\" aria-label=\"Permalink\">#</a></h1>\n\
+            <nav>\n<p><a href=\"#title\" title=\"class=x\">#</a></p>\n</nav>"
+        );
+        assert_eq!(sanitizer.remove_reserved_attributes("a < b <!-- <a id=x> --> </a id=x>"), "a < b <!-- <a id=x> --> </a id=x>");
+
+        let config = SanitizationConfig {
+            allowed_attributes: HashMap::from([("span".to_owned(), vec!["class".to_owned()])]),
+            ..Default::default()
+        };
+        let sanitizer = Sanitizer::new(&config).unwrap();
+        assert_eq!(render("<span class=\"note\" id=\"x\">Hi</span>", &sanitizer), "<p><span class=\"note\">Hi</span></p>\n");
+    }
+
+    #[test]
+    fn trust_account_editors() {
+        let mut config = SanitizationConfig::default();
+        let accounts = Access::Accounts(vec!["alex".into()]);
+        let for_article = |config: &SanitizationConfig, edit_access: &Access| {
+            Sanitizer::new(config).unwrap().for_article(edit_access).is_some()
+        };
+        assert!(for_article(&config, &accounts));
+        config.trust_account_editors = true;
+        assert!(!for_article(&config, &accounts));
+        assert!(for_article(&config, &Access::Anonymous));
+        assert!(for_article(&config, &Access::Authenticated));
+        config.enabled = false;
+        assert!(!for_article(&config, &Access::Anonymous));
+    }
+}
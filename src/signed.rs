@@ -0,0 +1,175 @@
+use crate::auth::Username;
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use snafu::Snafu;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a signed token may be used for. Included in the signature, so that a token issued for one
+/// purpose can't be used for another.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TokenPurpose {
+    /// Creating a new account with the username in the token.
+    Invite,
+    /// Setting a new password for the account with the username in the token.
+    PasswordReset,
+}
+
+impl TokenPurpose {
+    /// How long tokens for this purpose remain valid after being issued, in seconds.
+    pub fn lifetime_seconds(&self) -> u64 {
+        match self {
+            TokenPurpose::Invite => 7 * 24 * 60 * 60,
+            TokenPurpose::PasswordReset => 24 * 60 * 60,
+        }
+    }
+}
+
+/// The contents of a signed token. Anyone holding the token can read these, so they must not
+/// contain anything secret.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub purpose: TokenPurpose,
+    pub username: Username,
+    /// The time the token expires, in seconds since the unix epoch.
+    pub expires: u64,
+    /// A random value unique to the token, so that it can be recognized once used.
+    pub nonce: String,
+    /// A fingerprint of the account's password hash when the token was issued, so that the token stops
+    /// working once the password changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum SignedTokenError {
+    #[snafu(display("The link is malformed. Make sure it was copied completely."))]
+    Malformed,
+    #[snafu(display("The link is not valid."))]
+    InvalidSignature,
+    #[snafu(display("The link has expired."))]
+    Expired,
+}
+
+impl Claims {
+    pub fn new(purpose: TokenPurpose, username: Username) -> Claims {
+        use rand_core::RngCore;
+
+        let mut nonce = [0u8; 16];
+        rand_core::OsRng.fill_bytes(&mut nonce);
+        Claims {
+            purpose,
+            username,
+            expires: crate::unix_time() + purpose.lifetime_seconds(),
+            nonce: BASE64_URL_SAFE_NO_PAD.encode(nonce),
+            fingerprint: None,
+        }
+    }
+
+    pub fn with_fingerprint(self, fingerprint: String) -> Claims {
+        Claims {
+            fingerprint: Some(fingerprint),
+            ..self
+        }
+    }
+
+    /// Signs the claims with a key derived from the secret key, producing a URL-safe token.
+    pub fn sign(&self, secret_key: &[u8]) -> String {
+        let payload = serde_json::to_vec(self).expect("Claims serialization should never fail.");
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(payload);
+        let key = signing_key(secret_key, self.purpose);
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(mac(&key, &payload).finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// Verifies that the token was signed with the secret key for the specified purpose and hasn't
+    /// expired, returning its claims.
+    pub fn verify(token: &str, secret_key: &[u8], purpose: TokenPurpose) -> Result<Claims, SignedTokenError> {
+        let (payload, signature) = token.trim().split_once('.').ok_or(SignedTokenError::Malformed)?;
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| SignedTokenError::Malformed)?;
+        // Verify before parsing, so that unauthenticated input is never deserialized.
+        mac(&signing_key(secret_key, purpose), payload)
+            .verify_slice(&signature)
+            .map_err(|_| SignedTokenError::InvalidSignature)?;
+
+        let payload = BASE64_URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| SignedTokenError::Malformed)?;
+        let claims: Claims = serde_json::from_slice(&payload).map_err(|_| SignedTokenError::Malformed)?;
+        if claims.purpose != purpose {
+            return Err(SignedTokenError::InvalidSignature);
+        }
+        if claims.expires <= crate::unix_time() {
+            return Err(SignedTokenError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+/// Derives the key tokens for the purpose are signed with. The secret key also protects the cookies,
+/// so it's never used directly, and each purpose gets a key of its own.
+fn signing_key(secret_key: &[u8], purpose: TokenPurpose) -> Vec<u8> {
+    let label = format!("smolwik signed token: {purpose:?}");
+    mac(secret_key, &label).finalize().into_bytes().to_vec()
+}
+
+fn mac(key: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length.");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signed::*;
+    use std::assert_matches;
+
+    const KEY: &[u8] = &[7u8; 64];
+
+    #[test]
+    fn sign_and_verify() {
+        let token = Claims::new(TokenPurpose::Invite, "alex".into()).sign(KEY);
+        let claims = Claims::verify(&token, KEY, TokenPurpose::Invite).unwrap();
+        assert_eq!(claims.username, "alex".into());
+        assert_ne!(claims.nonce, Claims::new(TokenPurpose::Invite, "alex".into()).nonce);
+
+        assert_matches!(Claims::verify(&token, &[8u8; 64], TokenPurpose::Invite), Err(SignedTokenError::InvalidSignature));
+        assert_matches!(Claims::verify("not a token", KEY, TokenPurpose::Invite), Err(SignedTokenError::Malformed));
+        assert_matches!(Claims::verify(&token, KEY, TokenPurpose::PasswordReset), Err(SignedTokenError::InvalidSignature));
+
+        // Changing the claims invalidates the signature.
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = Claims::new(TokenPurpose::Invite, "morgan".into()).sign(KEY);
+        let (payload, _) = forged.split_once('.').unwrap();
+        let forged = format!("{payload}.{signature}");
+        assert_matches!(Claims::verify(&forged, KEY, TokenPurpose::Invite), Err(SignedTokenError::InvalidSignature));
+    }
+
+    #[test]
+    fn purpose_specific_keys() {
+        // Tokens aren't signed with the secret key itself, since it also protects the cookies.
+        let token = Claims::new(TokenPurpose::Invite, "alex".into()).sign(KEY);
+        let (payload, _) = token.split_once('.').unwrap();
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(mac(KEY, payload).finalize().into_bytes());
+        let forged = format!("{payload}.{signature}");
+        assert_matches!(Claims::verify(&forged, KEY, TokenPurpose::Invite), Err(SignedTokenError::InvalidSignature));
+
+        assert_ne!(signing_key(KEY, TokenPurpose::Invite), signing_key(KEY, TokenPurpose::PasswordReset));
+    }
+
+    #[test]
+    fn expired_token() {
+        let claims = Claims {
+            purpose: TokenPurpose::Invite,
+            username: "alex".into(),
+            expires: crate::unix_time() - 1,
+            nonce: "nonce".to_owned(),
+            fingerprint: None,
+        };
+        assert_matches!(Claims::verify(&claims.sign(KEY), KEY, TokenPurpose::Invite), Err(SignedTokenError::Expired));
+    }
+}
@@ -0,0 +1,356 @@
+use crate::ErrorResponse;
+use crate::auth::{AuthenticationMode, User, Username};
+use crate::config::LoginThrottlingConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks failed login attempts per user and client address pair, per client address, and per user.
+/// Once too many attempts have failed, further attempts are refused for a lockout period which doubles
+/// with each additional failure. Failures per user alone have a higher limit, since anyone can add to
+/// them and lock the user out.
+pub struct LoginThrottle {
+    config: LoginThrottlingConfig,
+    failures: Mutex<HashMap<ThrottleKey, FailureRecord>>,
+}
+
+/// Identifies who a login attempt is for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LoginTarget {
+    /// The single user, whose password is the only one in single-user mode.
+    SingleUser,
+    /// The account with the specified username.
+    Account(Username),
+}
+
+impl LoginTarget {
+    /// Gets the target of login attempts as the specified user. [None] for anonymous users.
+    pub fn for_user(user: &User) -> Option<LoginTarget> {
+        match user {
+            User::Anonymous => None,
+            User::SingleUser => Some(LoginTarget::SingleUser),
+            User::Account(username) => Some(LoginTarget::Account(username.clone())),
+        }
+    }
+
+    /// Gets the target of login attempts with the specified username under the specified
+    /// authentication mode. The username is ignored in single-user mode.
+    pub fn for_login(auth_mode: AuthenticationMode, username: Option<&Username>) -> Option<LoginTarget> {
+        match auth_mode {
+            AuthenticationMode::Single => Some(LoginTarget::SingleUser),
+            _ => username.cloned().map(LoginTarget::Account),
+        }
+    }
+}
+
+/// Identifies what a failed login attempt is counted against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ThrottleKey {
+    /// Failures against the target from the specified client address.
+    Target(LoginTarget, Option<IpAddr>),
+    /// Failures from the specified client address, against any target.
+    Address(IpAddr),
+    /// Failures against the target from any client address.
+    User(LoginTarget),
+}
+
+/// A login attempt. Failures are counted against the target from the client address, the client
+/// address alone, and the target alone, so that neither guessing many passwords for one user nor one
+/// password for many users is possible, even from many addresses.
+#[derive(Debug, Clone)]
+pub struct LoginAttempt {
+    target_key: Option<ThrottleKey>,
+    address_key: Option<ThrottleKey>,
+    user_key: Option<ThrottleKey>,
+    description: String,
+}
+
+impl LoginAttempt {
+    pub fn new(target: Option<LoginTarget>, address: Option<IpAddr>) -> LoginAttempt {
+        let mut description = match &target {
+            Some(LoginTarget::Account(username)) => format!("for `{username}`"),
+            Some(LoginTarget::SingleUser) => "for the single user".to_owned(),
+            None => "without a username".to_owned(),
+        };
+        if let Some(address) = address {
+            description = format!("{description} from {address}");
+        }
+
+        LoginAttempt {
+            user_key: target.clone().map(ThrottleKey::User),
+            target_key: target.map(|target| ThrottleKey::Target(target, address)),
+            address_key: address.map(ThrottleKey::Address),
+            description,
+        }
+    }
+
+    fn keys(&self) -> Vec<ThrottleKey> {
+        self.target_key.iter().chain(&self.address_key).chain(&self.user_key).cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FailureRecord {
+    /// The number of consecutive failed attempts.
+    count: u32,
+    /// The time of the most recent failed attempt.
+    last_failure: Instant,
+    /// The time until which further attempts are refused, if locked out.
+    locked_until: Option<Instant>,
+}
+
+impl LoginThrottle {
+    pub fn new(config: LoginThrottlingConfig) -> LoginThrottle {
+        LoginThrottle {
+            config,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks if the login attempt is allowed, returning an error response if it's locked out. An
+    /// allowed attempt is counted as failed until [Self::record_successful_attempt] is called, so
+    /// that concurrent attempts can't all pass the check before any of them fail.
+    pub fn begin_attempt(&self, attempt: &LoginAttempt) -> Result<(), ErrorResponse> {
+        match self.begin_at(&attempt.keys(), Instant::now()) {
+            Ok(None) => Ok(()),
+            Ok(Some(lockout)) => {
+                eprintln!(
+                    "WARN: Too many login attempts {}. Locked out for {} seconds.",
+                    attempt.description,
+                    lockout.as_secs()
+                );
+                Ok(())
+            }
+            Err(remaining) => {
+                eprintln!("WARN: Refused login attempt {} during lockout.", attempt.description);
+                Err(ErrorResponse::too_many_login_attempts(remaining))
+            }
+        }
+    }
+
+    /// Records the login attempt as successful. The target's failures are cleared, but only the
+    /// attempt itself is taken back from the address - clearing the address would let someone with a
+    /// valid account reset their own lockout while guessing the passwords of others.
+    pub fn record_successful_attempt(&self, attempt: &LoginAttempt) {
+        let mut failures = self.failures.lock().expect("Throttle lock should never be poisoned.");
+        for key in attempt.target_key.iter().chain(&attempt.user_key) {
+            failures.remove(key);
+        }
+        if let Some(key) = &attempt.address_key {
+            self.take_back(&mut failures, key);
+        }
+    }
+
+    /// Records the login attempt as neither failed nor successful, such as when the password was
+    /// correct but a second factor is still required. Earlier failures are kept.
+    pub fn record_partial_attempt(&self, attempt: &LoginAttempt) {
+        let mut failures = self.failures.lock().expect("Throttle lock should never be poisoned.");
+        for key in attempt.keys() {
+            self.take_back(&mut failures, &key);
+        }
+    }
+
+    /// Takes back one attempt counted against the key, lifting its lockout if that brings it below the limit.
+    fn take_back(&self, failures: &mut HashMap<ThrottleKey, FailureRecord>, key: &ThrottleKey) {
+        if let Some(record) = failures.get_mut(key) {
+            record.count = record.count.saturating_sub(1);
+            if record.count < self.max_attempts(key) {
+                record.locked_until = None;
+            }
+        }
+    }
+
+    /// Checks if an attempt against any of the specified keys is allowed, and if so, records it as
+    /// failed against each key while still holding the lock. Returns the remaining duration of the
+    /// longest active lockout if not allowed, otherwise the duration of any lockout the attempt caused.
+    fn begin_at(&self, keys: &[ThrottleKey], now: Instant) -> Result<Option<Duration>, Duration> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+
+        let mut failures = self.failures.lock().expect("Throttle lock should never be poisoned.");
+        let remaining = keys
+            .iter()
+            .filter_map(|key| failures.get(key))
+            .filter_map(|record| record.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+            .max();
+        if let Some(remaining) = remaining {
+            return Err(remaining);
+        }
+
+        let reset_after = Duration::from_secs(self.config.reset_seconds);
+        // Forget failures that are old enough to no longer matter, so that the map can't grow without
+        // bound from attempts against non-existent usernames.
+        failures.retain(|_, record| now.duration_since(record.last_failure) < reset_after);
+
+        let mut lockout = None;
+        for key in keys.iter().filter(|key| self.max_attempts(key) > 0) {
+            let record = failures.entry(key.clone()).or_insert(FailureRecord {
+                count: 0,
+                last_failure: now,
+                locked_until: None,
+            });
+            record.count += 1;
+            record.last_failure = now;
+            if let Some(duration) = self.lockout_duration(self.max_attempts(key), record.count) {
+                record.locked_until = Some(now + duration);
+                lockout = lockout.max(Some(duration));
+            }
+        }
+        Ok(lockout)
+    }
+
+    /// Gets the number of consecutive failures allowed against the key. Zero if failures aren't counted.
+    fn max_attempts(&self, key: &ThrottleKey) -> u32 {
+        match key {
+            ThrottleKey::Target(..) | ThrottleKey::Address(_) => self.config.max_attempts,
+            ThrottleKey::User(_) => self.config.max_user_attempts,
+        }
+    }
+
+    /// Calculates the lockout duration after the specified number of consecutive failures. Returns
+    /// [None] if the number of failures is still below the allowed maximum.
+    fn lockout_duration(&self, max_attempts: u32, count: u32) -> Option<Duration> {
+        if count < max_attempts {
+            return None;
+        }
+
+        let exponent = (count - max_attempts).min(31);
+        let seconds = self
+            .config
+            .lockout_seconds
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_lockout_seconds);
+        Some(Duration::from_secs(seconds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::LoginThrottlingConfig;
+    use crate::throttle::*;
+    use std::assert_matches;
+
+    fn config() -> LoginThrottlingConfig {
+        LoginThrottlingConfig {
+            enabled: true,
+            max_attempts: 3,
+            max_user_attempts: 5,
+            lockout_seconds: 10,
+            max_lockout_seconds: 60,
+            reset_seconds: 3600,
+        }
+    }
+
+    fn account(name: &str) -> ThrottleKey {
+        ThrottleKey::Target(LoginTarget::Account(name.into()), None)
+    }
+
+    #[test]
+    fn lockout_after_max_attempts() {
+        let throttle = LoginThrottle::new(config());
+        let keys = [account("alex")];
+        let now = Instant::now();
+
+        assert_eq!(throttle.begin_at(&keys, now), Ok(None));
+        assert_eq!(throttle.begin_at(&keys, now), Ok(None));
+        assert_eq!(throttle.begin_at(&keys, now), Ok(Some(Duration::from_secs(10))));
+        assert_matches!(throttle.begin_at(&keys, now), Err(_));
+        assert_eq!(throttle.begin_at(&keys, now + Duration::from_secs(10)), Ok(Some(Duration::from_secs(20))));
+
+        // Other keys are unaffected.
+        assert_eq!(throttle.begin_at(&[account("morgan")], now), Ok(None));
+    }
+
+    #[test]
+    fn lockout_backs_off_exponentially() {
+        let throttle = LoginThrottle::new(config());
+        let keys = [ThrottleKey::Target(LoginTarget::SingleUser, None)];
+        let mut now = Instant::now();
+
+        for _ in 0..3 {
+            throttle.begin_at(&keys, now).unwrap();
+        }
+        for expected in [20, 40, 60, 60] {
+            now += Duration::from_secs(60);
+            assert_eq!(throttle.begin_at(&keys, now), Ok(Some(Duration::from_secs(expected))));
+        }
+    }
+
+    #[test]
+    fn lockout_per_address() {
+        let throttle = LoginThrottle::new(config());
+        let attempt = |address: [u8; 4]| {
+            let target = LoginTarget::for_login(AuthenticationMode::Multi, Some(&"alex".into()));
+            LoginAttempt::new(target, Some(address.into()))
+        };
+
+        for _ in 0..3 {
+            throttle.begin_attempt(&attempt([192, 168, 1, 20])).unwrap();
+        }
+        assert_matches!(throttle.begin_attempt(&attempt([192, 168, 1, 20])), Err(_));
+        // Failures from elsewhere don't lock the user out.
+        assert_matches!(throttle.begin_attempt(&attempt([192, 168, 1, 30])), Ok(()));
+    }
+
+    #[test]
+    fn lockout_per_user_from_many_addresses() {
+        let throttle = LoginThrottle::new(config());
+        let attempt = |address: u8| {
+            let target = LoginTarget::for_login(AuthenticationMode::Multi, Some(&"alex".into()));
+            LoginAttempt::new(target, Some([192, 168, 1, address].into()))
+        };
+
+        // Each address stays below its own limit, but the user's limit is reached.
+        for address in 0..5 {
+            throttle.begin_attempt(&attempt(address)).unwrap();
+        }
+        assert_matches!(throttle.begin_attempt(&attempt(5)), Err(_));
+        let other = LoginAttempt::new(Some(LoginTarget::Account("morgan".into())), Some([192, 168, 1, 5].into()));
+        assert_matches!(throttle.begin_attempt(&other), Ok(()));
+
+        // The limit can be turned off, for when locking users out is more of a concern.
+        let throttle = LoginThrottle::new(LoginThrottlingConfig {
+            max_user_attempts: 0,
+            ..config()
+        });
+        for address in 0..10 {
+            throttle.begin_attempt(&attempt(address)).unwrap();
+        }
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let throttle = LoginThrottle::new(config());
+        let attempt = LoginAttempt::new(Some(LoginTarget::SingleUser), Some([127, 0, 0, 1].into()));
+
+        for _ in 0..2 {
+            throttle.begin_attempt(&attempt).unwrap();
+        }
+        // The third attempt reaches the limit, but succeeds, so doesn't count against either key.
+        throttle.begin_attempt(&attempt).unwrap();
+        throttle.record_successful_attempt(&attempt);
+        assert_matches!(throttle.begin_attempt(&attempt), Ok(()));
+
+        // The address keeps its earlier failures, unlike the target.
+        let other = LoginAttempt::new(Some(LoginTarget::Account("morgan".into())), Some([127, 0, 0, 1].into()));
+        assert_matches!(throttle.begin_attempt(&other), Err(_));
+    }
+
+    #[test]
+    fn disabled_never_locks_out() {
+        let throttle = LoginThrottle::new(LoginThrottlingConfig {
+            enabled: false,
+            ..config()
+        });
+        let keys = [ThrottleKey::Target(LoginTarget::SingleUser, None)];
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(throttle.begin_at(&keys, now), Ok(None));
+        }
+    }
+}
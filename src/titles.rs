@@ -0,0 +1,96 @@
+use crate::auth::{Access, Authorization, User};
+use crate::metadata::Metadata;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+/// The title and view access of an article, as of when it was last indexed.
+#[derive(Debug, Clone)]
+pub struct IndexedArticle {
+    pub url: String,
+    pub title: String,
+    pub view_access: Access,
+}
+
+impl IndexedArticle {
+    /// Checks if the viewer may see the article. [None] views every article, such as when indexing links.
+    pub fn is_visible_to(&self, viewer: Option<&User>) -> bool {
+        viewer.is_none_or(|user| user.check_authorization(&self.view_access) == Authorization::Authorized)
+    }
+}
+
+/// The titles of every article, used to resolve `[[wiki links]]` without reading the article
+/// directory. Filled in when links are indexed at startup, and updated whenever an article is saved.
+#[derive(Default)]
+pub struct TitleIndex {
+    /// The indexed articles by URL. Ordered, so that the first article with a title is always the same.
+    articles: RwLock<BTreeMap<String, IndexedArticle>>,
+}
+
+impl TitleIndex {
+    /// Adds or replaces the article at the specified URL. Returns the previous title, if it was indexed.
+    pub fn insert(&self, url: &str, metadata: &Metadata) -> Option<String> {
+        let article = IndexedArticle {
+            url: url.to_owned(),
+            title: metadata.title.clone(),
+            view_access: metadata.view_access.clone(),
+        };
+        let mut articles = self.articles.write().expect("Title index lock poisoned.");
+        articles.insert(url.to_owned(), article).map(|previous| previous.title)
+    }
+
+    /// Gets the article at the specified URL.
+    pub fn get(&self, url: &str) -> Option<IndexedArticle> {
+        self.articles.read().expect("Title index lock poisoned.").get(url).cloned()
+    }
+
+    /// Finds the article at a path relative to the article root, or the index of the directory at the path.
+    pub fn find_path(&self, path: &str) -> Option<IndexedArticle> {
+        let path = path.trim_matches('/');
+        let articles = self.articles.read().expect("Title index lock poisoned.");
+        let index = match path.is_empty() {
+            true => "/index".to_owned(),
+            false => format!("/{path}/index"),
+        };
+        articles.get(&format!("/{path}")).or_else(|| articles.get(&index)).cloned()
+    }
+
+    /// Finds the article with the specified title, ignoring case. If several have the title, the one
+    /// with the first URL the viewer may see is found.
+    pub fn find_title(&self, title: &str, viewer: Option<&User>) -> Option<IndexedArticle> {
+        let articles = self.articles.read().expect("Title index lock poisoned.");
+        articles
+            .values()
+            .filter(|article| article.title.to_lowercase() == title.to_lowercase())
+            .find(|article| article.is_visible_to(viewer))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::titles::*;
+
+    #[test]
+    fn find_articles() {
+        let titles = TitleIndex::default();
+        let metadata = |title: &str, view_access: Access| Metadata {
+            title: title.to_owned(),
+            view_access,
+            ..Default::default()
+        };
+        titles.insert("/house/index", &metadata("House", Access::Anonymous));
+        titles.insert("/notes/b", &metadata("Notes", Access::Anonymous));
+        assert_eq!(titles.insert("/notes/a", &metadata("Draft", Access::Authenticated)), None);
+        assert_eq!(titles.insert("/notes/a", &metadata("Notes", Access::Authenticated)), Some("Draft".to_owned()));
+
+        assert_eq!(titles.find_path("house").unwrap().url, "/house/index");
+        assert_eq!(titles.find_path("/notes/a").unwrap().title, "Notes");
+        assert!(titles.find_path("notes/c").is_none());
+
+        // Articles the viewer can't see are passed over.
+        assert_eq!(titles.find_title("NOTES", None).unwrap().url, "/notes/a");
+        assert_eq!(titles.find_title("notes", Some(&User::Anonymous)).unwrap().url, "/notes/b");
+        assert_eq!(titles.find_title("house", Some(&User::Anonymous)).unwrap().url, "/house/index");
+        assert!(titles.find_title("draft", None).is_none());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::auth::User;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// The issuer shown in authenticator apps.
+const ISSUER: &str = "smolwik";
+
+/// Generates a new random 160-bit TOTP secret, encoded as base32.
+pub fn generate_secret() -> String {
+    use rand_core::RngCore;
+
+    let mut bytes = vec![0u8; 20];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    Secret::Raw(bytes).to_encoded().to_string()
+}
+
+/// Checks if the specified secret is a valid base32 encoded TOTP secret.
+pub fn validate_secret(secret: &str) -> bool {
+    Secret::Encoded(secret.to_owned()).to_bytes().is_ok_and(|bytes| bytes.len() >= 16)
+}
+
+/// Verifies the code against the base32 encoded secret, allowing for one step of clock skew. Returns
+/// the time step the code is for, or [None] if it's invalid.
+fn verify_step(secret: &str, code: &str, now: u64) -> Option<u64> {
+    let mut totp = build(secret, "")?;
+    let current = now / totp.step;
+    // Each step is checked on its own, to find which one the code is for.
+    totp.skew = 0;
+    (current.saturating_sub(1)..=current + 1).find(|step| totp.check(code.trim(), step * totp.step))
+}
+
+/// The time step of the last code accepted from each user. Codes are valid for a while, so without
+/// this, a code seen over someone's shoulder or captured in transit could be used again.
+#[derive(Default)]
+pub struct UsedCodes {
+    last_steps: Mutex<BTreeMap<User, u64>>,
+}
+
+impl UsedCodes {
+    /// Verifies the user's code against the base32 encoded secret, allowing for one step of clock
+    /// skew. Codes for the step of the last code accepted from the user, or earlier, are rejected.
+    pub fn verify(&self, user: &User, secret: &str, code: &str) -> bool {
+        self.verify_at(user, secret, code, crate::unix_time())
+    }
+
+    fn verify_at(&self, user: &User, secret: &str, code: &str, now: u64) -> bool {
+        let Some(step) = verify_step(secret, code, now) else {
+            return false;
+        };
+        let mut last_steps = self.last_steps.lock().expect("Used codes lock poisoned.");
+        match last_steps.get(user) {
+            Some(last_step) if step <= *last_step => false,
+            _ => {
+                last_steps.insert(user.clone(), step);
+                true
+            }
+        }
+    }
+}
+
+/// Builds the `otpauth://` URI used to enroll the secret in an authenticator app.
+pub fn otpauth_uri(secret: &str, account_name: &str) -> Option<String> {
+    build(secret, account_name).map(|totp| totp.get_url())
+}
+
+/// Renders the URI as a QR code in SVG format, suitable for embedding directly in a page.
+pub fn qr_code_svg(uri: &str) -> Option<String> {
+    use qrcode::QrCode;
+    use qrcode::render::svg;
+
+    let code = QrCode::new(uri.as_bytes()).ok()?;
+    let svg = code.render::<svg::Color>().min_dimensions(200, 200).build();
+    // The XML declaration isn't valid in HTML, so strip it.
+    Some(svg.trim_start_matches(r#"<?xml version="1.0" standalone="yes"?>"#).to_owned())
+}
+
+fn build(secret: &str, account_name: &str) -> Option<TOTP> {
+    let bytes = Secret::Encoded(secret.to_owned()).to_bytes().ok()?;
+    // The unchecked constructor is used since usernames may contain characters, such as `:`, that
+    // the checked constructor rejects.
+    Some(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        bytes,
+        Some(ISSUER.to_owned()),
+        account_name.to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::totp::*;
+
+    #[test]
+    fn generated_secret_verifies_current_code() {
+        let secret = generate_secret();
+        assert!(validate_secret(&secret));
+
+        let used = UsedCodes::default();
+        let code = build(&secret, "").unwrap().generate_current().unwrap();
+        assert!(!used.verify(&User::SingleUser, &secret, "not a code"));
+        assert!(!used.verify(&User::SingleUser, "not a secret", &code));
+        assert!(used.verify(&User::SingleUser, &secret, &code));
+    }
+
+    #[test]
+    fn codes_only_accepted_once() {
+        let secret = generate_secret();
+        let totp = build(&secret, "").unwrap();
+        let used = UsedCodes::default();
+        let now = 1_000_000_020;
+        let code = |time: u64| totp.generate(time);
+
+        assert!(used.verify_at(&User::SingleUser, &secret, &code(now), now));
+        assert!(!used.verify_at(&User::SingleUser, &secret, &code(now), now));
+        // Nor can a code from before the one accepted be used, even while it's still valid.
+        assert!(!used.verify_at(&User::SingleUser, &secret, &code(now - 30), now));
+        assert!(used.verify_at(&User::SingleUser, &secret, &code(now + 30), now));
+
+        // Each user's codes are tracked separately.
+        assert!(used.verify_at(&User::Account("alex".into()), &secret, &code(now), now));
+    }
+
+    #[test]
+    fn otpauth_uri_contains_issuer_and_account() {
+        let secret = generate_secret();
+        let uri = otpauth_uri(&secret, "alex").unwrap();
+        assert!(uri.starts_with("otpauth://totp/smolwik:alex?"));
+        assert!(uri.contains(&format!("secret={secret}")));
+    }
+}